@@ -0,0 +1,355 @@
+//! Sprite-sheet animation of the fighters.
+//!
+//! Each role has an animation file, `assets/animations/<role>.anim`, naming
+//! its sheet and which frames of it every clip plays:
+//!
+//! ```text
+//! sheet = boxer_sheet.png 23 91 7 2
+//! idle = 0 2 2
+//! punch = 6 3 6 once
+//! ```
+//!
+//! `sheet` is the image, the size of one frame in pixels and how many
+//! columns and rows of frames it has. A clip line gives the first frame,
+//! counted row by row from the top left, the number of frames and the frames
+//! per second; clips marked `once` stop on their last frame instead of
+//! looping. The clips are `idle`, `walk`, `jump`, `fall`, `punch`, `hit` and
+//! `ko`, a clip that is left out shows the first frame. The clip follows what
+//! the fighter is doing and the sprite is mirrored when they face left.
+
+use bevy::prelude::*;
+
+use std::io;
+
+use crate::game_time::GameTime;
+use crate::sim::{FighterState, Role};
+use crate::{Body, Sim};
+
+#[cfg(not(target_arch = "wasm32"))]
+const ANIMATION_DIR: &str = "assets/animations";
+
+// the browser build has no file system to read the animations from
+#[cfg(target_arch = "wasm32")]
+const SHIPPED_ANIMATIONS: [(&str, &str); 2] = [
+    ("boxer", include_str!("../assets/animations/boxer.anim")),
+    (
+        "weapon_specialist",
+        include_str!("../assets/animations/weapon_specialist.anim"),
+    ),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Clip {
+    Idle,
+    Walk,
+    Jump,
+    Fall,
+    Punch,
+    Hit,
+    Ko,
+}
+
+impl Clip {
+    const ALL: [Clip; 7] = [
+        Clip::Idle,
+        Clip::Walk,
+        Clip::Jump,
+        Clip::Fall,
+        Clip::Punch,
+        Clip::Hit,
+        Clip::Ko,
+    ];
+
+    fn key(self) -> &'static str {
+        match self {
+            Clip::Idle => "idle",
+            Clip::Walk => "walk",
+            Clip::Jump => "jump",
+            Clip::Fall => "fall",
+            Clip::Punch => "punch",
+            Clip::Hit => "hit",
+            Clip::Ko => "ko",
+        }
+    }
+
+    /// what `fighter` is doing.
+    pub fn of(fighter: &FighterState) -> Clip {
+        if fighter.lives == 0 {
+            Clip::Ko
+        } else if fighter.is_hit() {
+            Clip::Hit
+        } else if fighter.gloves.punch_ticks > 0 && fighter.role == Role::Boxer {
+            Clip::Punch
+        } else if !fighter.on_ground && fighter.vel.y > 0.0 {
+            Clip::Jump
+        } else if !fighter.on_ground {
+            Clip::Fall
+        } else if fighter.vel.x != 0.0 {
+            Clip::Walk
+        } else {
+            Clip::Idle
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipFrames {
+    pub first: usize,
+    pub count: usize,
+    pub fps: f32,
+    /// stops on the last frame instead of looping
+    pub once: bool,
+}
+
+impl Default for ClipFrames {
+    fn default() -> Self {
+        ClipFrames {
+            first: 0,
+            count: 1,
+            fps: 1.0,
+            once: false,
+        }
+    }
+}
+
+impl ClipFrames {
+    /// the frame shown `seconds` into the clip.
+    pub fn frame(&self, seconds: f32) -> usize {
+        let frame = (seconds * self.fps) as usize;
+        if self.once {
+            self.first + frame.min(self.count - 1)
+        } else {
+            self.first + frame % self.count
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationSheet {
+    /// asset path of the image
+    pub image: String,
+    /// of one frame, in pixels
+    pub frame_size: Vec2,
+    pub columns: usize,
+    pub rows: usize,
+    /// by `Clip`
+    pub clips: [ClipFrames; 7],
+}
+
+impl AnimationSheet {
+    pub fn from_text(name: &str, text: &str) -> Option<AnimationSheet> {
+        let mut sheet = None;
+        let mut clips = [ClipFrames::default(); 7];
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = line.split_once('=').and_then(|(key, value)| {
+                let (key, value) = (key.trim(), value.trim());
+                if key == "sheet" {
+                    sheet = Some(parse_sheet(value)?);
+                } else {
+                    let clip = Clip::ALL.into_iter().find(|clip| clip.key() == key)?;
+                    clips[clip as usize] = parse_clip(value)?;
+                }
+                Some(())
+            });
+            if parsed.is_none() {
+                warn!("ignoring invalid line in animation {}: {}", name, line);
+            }
+        }
+        let (image, frame_size, columns, rows) = sheet?;
+        // frames past the end of the sheet show the first one
+        for clip in clips.iter_mut() {
+            if clip.first + clip.count > columns * rows {
+                *clip = ClipFrames::default();
+            }
+        }
+        Some(AnimationSheet {
+            image,
+            frame_size,
+            columns,
+            rows,
+            clips,
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(name: &str) -> io::Result<AnimationSheet> {
+        let path = std::path::Path::new(ANIMATION_DIR).join(format!("{}.anim", name));
+        let text = std::fs::read_to_string(path)?;
+        AnimationSheet::from_text(name, &text)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no sheet"))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load(name: &str) -> io::Result<AnimationSheet> {
+        SHIPPED_ANIMATIONS
+            .iter()
+            .find(|(shipped, _)| *shipped == name)
+            .and_then(|(_, text)| AnimationSheet::from_text(name, text))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such animation"))
+    }
+
+    /// the sheet of `role`, or its still image when the sheet can't be
+    /// read.
+    pub fn load_or_still(role: Role) -> AnimationSheet {
+        let name = animation_name(role);
+        AnimationSheet::load(name).unwrap_or_else(|err| {
+            warn!("could not load animation {}: {}", name, err);
+            AnimationSheet {
+                image: crate::fighter_image(role).to_string(),
+                frame_size: Vec2::new(23.0, 91.0),
+                columns: 1,
+                rows: 1,
+                clips: [ClipFrames::default(); 7],
+            }
+        })
+    }
+
+    pub fn clip(&self, clip: Clip) -> &ClipFrames {
+        &self.clips[clip as usize]
+    }
+}
+
+/// `<image> <width> <height> <columns> <rows>`
+fn parse_sheet(value: &str) -> Option<(String, Vec2, usize, usize)> {
+    let words: Vec<&str> = value.split_whitespace().collect();
+    match words.as_slice() {
+        [image, width, height, columns, rows] => {
+            let size = Vec2::new(width.parse().ok()?, height.parse().ok()?);
+            let (columns, rows): (usize, usize) = (columns.parse().ok()?, rows.parse().ok()?);
+            (size.min_element() > 0.0 && columns > 0 && rows > 0)
+                .then(|| (image.to_string(), size, columns, rows))
+        }
+        _ => None,
+    }
+}
+
+/// `<first> <count> <fps>`, optionally followed by `once`.
+fn parse_clip(value: &str) -> Option<ClipFrames> {
+    let words: Vec<&str> = value.split_whitespace().collect();
+    let (numbers, once) = match words.as_slice() {
+        [numbers @ .., "once"] => (numbers, true),
+        numbers => (numbers, false),
+    };
+    match numbers {
+        [first, count, fps] => {
+            let clip = ClipFrames {
+                first: first.parse().ok()?,
+                count: count.parse().ok()?,
+                fps: fps.parse().ok()?,
+                once,
+            };
+            (clip.count > 0 && clip.fps > 0.0).then(|| clip)
+        }
+        _ => None,
+    }
+}
+
+fn animation_name(role: Role) -> &'static str {
+    match role {
+        Role::Boxer => "boxer",
+        Role::WeaponSpecialist => "weapon_specialist",
+    }
+}
+
+/// the sheets of both roles, loaded once at startup.
+pub struct Animations {
+    sheets: [AnimationSheet; 2],
+    atlases: [Handle<TextureAtlas>; 2],
+}
+
+impl FromWorld for Animations {
+    fn from_world(world: &mut World) -> Self {
+        let sheets = [Role::Boxer, Role::WeaponSpecialist].map(AnimationSheet::load_or_still);
+        let images = {
+            let server = world.get_resource::<AssetServer>().unwrap();
+            [0, 1].map(|i| server.load::<Image, _>(sheets[i].image.as_str()))
+        };
+        let mut atlases = world.get_resource_mut::<Assets<TextureAtlas>>().unwrap();
+        let atlases = [0, 1].map(|i| {
+            let sheet = &sheets[i];
+            atlases.add(TextureAtlas::from_grid(
+                images[i].clone(),
+                sheet.frame_size,
+                sheet.columns,
+                sheet.rows,
+            ))
+        });
+        Animations { sheets, atlases }
+    }
+}
+
+impl Animations {
+    fn index(role: Role) -> usize {
+        match role {
+            Role::Boxer => 0,
+            Role::WeaponSpecialist => 1,
+        }
+    }
+
+    pub fn sheet(&self, role: Role) -> &AnimationSheet {
+        &self.sheets[Animations::index(role)]
+    }
+
+    pub fn atlas(&self, role: Role) -> Handle<TextureAtlas> {
+        self.atlases[Animations::index(role)].clone()
+    }
+}
+
+#[derive(Component, Default)]
+pub struct Animation {
+    clip: Option<Clip>,
+    /// seconds since the clip started
+    time: f32,
+}
+
+impl Animation {
+    /// plays `clip` for another `seconds`, from its start when it is a
+    /// different one than before.
+    pub fn advance(&mut self, clip: Clip, seconds: f32) {
+        if self.clip != Some(clip) {
+            self.clip = Some(clip);
+            self.time = 0.0;
+        } else {
+            self.time += seconds;
+        }
+    }
+
+    /// the frame of `sheet` shown now.
+    pub fn frame(&self, sheet: &AnimationSheet) -> usize {
+        self.clip
+            .map_or(0, |clip| sheet.clip(clip).frame(self.time))
+    }
+}
+
+/// plays the clip of what each fighter is doing, mirrored when they face
+/// left.
+pub fn animate_fighters(
+    game_time: Res<GameTime>,
+    sim: Res<Sim>,
+    animations: Res<Animations>,
+    mut body_query: Query<(
+        &Body,
+        &mut Animation,
+        &mut TextureAtlasSprite,
+        &mut Handle<TextureAtlas>,
+    )>,
+) {
+    for (body, mut animation, mut sprite, mut atlas) in body_query.iter_mut() {
+        let fighter = sim.simulation.fighter(body.fighter);
+        // the opponent's role changes with the match settings
+        let role_atlas = animations.atlas(fighter.role);
+        if *atlas != role_atlas {
+            *atlas = role_atlas;
+        }
+
+        animation.advance(Clip::of(fighter), game_time.delta_seconds());
+        let frame = animation.frame(animations.sheet(fighter.role));
+        if sprite.index != frame {
+            sprite.index = frame;
+        }
+        sprite.flip_x = fighter.facing < 0.0;
+    }
+}
@@ -0,0 +1,292 @@
+//! Sound effects and the volume channels.
+//!
+//! The sounds are loaded once at startup and played when the simulation
+//! reports what happened, nothing else calls into the audio directly. Every
+//! effect gets a voice, a channel of its own, so its volume can be set on
+//! its own; there are `VOICES` of them and one sound plays at most
+//! `MAX_INSTANCES` times at once, the oldest gets cut off for a new one.
+//! Master, effects and music volume come from the settings. Effects are
+//! panned by where they happen on the screen and get quieter the further off
+//! screen they are.
+//!
+//! The music follows the game: the stage picks a track for the title screen,
+//! the fight, the tension once someone is down to their last lives and for
+//! the end of the match, see `src/stage.rs`. When the track changes the new
+//! one fades in over `CROSSFADE` seconds on one of two music channels while
+//! the old one fades out on the other.
+
+use bevy::prelude::*;
+use bevy_kira_audio::{Audio, AudioChannel, AudioSource};
+
+use crate::camera::CameraRig;
+use crate::settings::Settings;
+use crate::sim::{Control, Fighter, SimEvent, Simulation};
+use crate::stage::Track;
+use crate::{GameState, Sim};
+
+const VOICES: usize = 12;
+// how many times one sound can play over itself
+const MAX_INSTANCES: usize = 3;
+// how far to the side a sound at the edge of the screen is panned, 1 is
+// all the way
+const PAN_WIDTH: f32 = 0.8;
+// how much quieter sounds get per half a screen off screen
+const OFF_SCREEN_FALLOFF: f32 = 2.0;
+// seconds from one piece of music to the next
+const CROSSFADE: f32 = 1.5;
+// the tension music starts once a fighter has this much of their lives left
+const TENSION_LIVES: f32 = 0.25;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sound {
+    Punch,
+    Shot,
+    Hit,
+}
+
+impl Sound {
+    const ALL: [Sound; 3] = [Sound::Punch, Sound::Shot, Sound::Hit];
+
+    fn path(self) -> &'static str {
+        match self {
+            Sound::Punch => "audio/fist-punch-or-kick-7171.ogg",
+            Sound::Shot => "audio/9mm-pistol-shot-6349.ogg",
+            Sound::Hit => "audio/hit-someting-6037.ogg",
+        }
+    }
+
+    /// how long the file plays, in seconds
+    fn length(self) -> f64 {
+        match self {
+            Sound::Punch => 0.96,
+            Sound::Shot => 1.69,
+            Sound::Hit => 1.08,
+        }
+    }
+
+    /// the sound of a simulation event, if it makes one.
+    fn of(event: &SimEvent) -> Option<Sound> {
+        match event {
+            SimEvent::Punch { .. } => Some(Sound::Punch),
+            SimEvent::Shot { .. } => Some(Sound::Shot),
+            SimEvent::Hit { .. } | SimEvent::KnockOut { .. } => Some(Sound::Hit),
+            SimEvent::Land { .. } | SimEvent::Impact { .. } => None,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Voice {
+    /// what the voice plays or played last
+    sound: Option<Sound>,
+    started: f64,
+    /// when the sound is over, in seconds since startup
+    until: f64,
+}
+
+pub struct GameAudio {
+    sounds: Vec<Handle<AudioSource>>,
+    voices: Vec<Voice>,
+    voice_channels: Vec<AudioChannel>,
+    music_channels: [AudioChannel; 2],
+    /// the music channel of the current track, the other one fades out
+    music_channel: usize,
+    /// the asset path of the current track
+    music: Option<String>,
+    /// from 0 when the track changes to 1 once the crossfade is over
+    fade: f32,
+    /// who lost the last match, until the next one starts
+    knocked_out: Option<Fighter>,
+}
+
+impl FromWorld for GameAudio {
+    fn from_world(world: &mut World) -> Self {
+        let server = world.get_resource::<AssetServer>().unwrap();
+        GameAudio {
+            sounds: Sound::ALL
+                .iter()
+                .map(|sound| server.load(sound.path()))
+                .collect(),
+            voices: vec![Voice::default(); VOICES],
+            voice_channels: (0..VOICES)
+                .map(|voice| AudioChannel::new(format!("voice{}", voice)))
+                .collect(),
+            music_channels: [
+                AudioChannel::new("music0".to_string()),
+                AudioChannel::new("music1".to_string()),
+            ],
+            music_channel: 0,
+            music: None,
+            fade: 1.0,
+            knocked_out: None,
+        }
+    }
+}
+
+impl GameAudio {
+    /// picks the voice for a new `sound` at `now`: the oldest of the sound
+    /// if it already plays `MAX_INSTANCES` times, else a free voice or the
+    /// oldest of all.
+    fn voice_for(&self, sound: Sound, now: f64) -> usize {
+        let playing = |voice: &Voice| voice.until > now;
+        let same: Vec<usize> = (0..VOICES)
+            .filter(|&index| {
+                let voice = &self.voices[index];
+                playing(voice) && voice.sound == Some(sound)
+            })
+            .collect();
+        let candidates = if same.len() >= MAX_INSTANCES {
+            same
+        } else if let Some(free) = self.voices.iter().position(|voice| !playing(voice)) {
+            return free;
+        } else {
+            (0..VOICES).collect()
+        };
+        candidates
+            .into_iter()
+            .min_by(|&a, &b| {
+                let (a, b) = (self.voices[a].started, self.voices[b].started);
+                a.partial_cmp(&b).unwrap()
+            })
+            .unwrap_or(0)
+    }
+
+    /// plays `sound` at `volume` and `panning`, from 0 on the left to 1 on
+    /// the right.
+    pub fn play(&mut self, audio: &Audio, sound: Sound, volume: f32, panning: f32, now: f64) {
+        let index = self.voice_for(sound, now);
+        let channel = &self.voice_channels[index];
+        audio.stop_channel(channel);
+        audio.set_volume_in_channel(volume, channel);
+        audio.set_panning_in_channel(panning, channel);
+        audio.play_in_channel(self.sounds[sound as usize].clone(), channel);
+        self.voices[index] = Voice {
+            sound: Some(sound),
+            started: now,
+            until: now + sound.length(),
+        };
+    }
+}
+
+/// the volume factor and panning of a sound at `pos` heard from `rig`, with
+/// `view` the window size in pixels.
+fn positional(rig: &CameraRig, view: Vec2, pos: Vec2) -> (f32, f32) {
+    let half = view * rig.zoom / 2.0;
+    if half.min_element() <= 0.0 {
+        return (1.0, 0.5);
+    }
+    // -1 to 1 from one edge of the screen to the other
+    let screen = (pos - rig.center) / half;
+    let off_screen = (screen.abs() - Vec2::ONE).max(Vec2::ZERO).length();
+    let volume = 1.0 / (1.0 + OFF_SCREEN_FALLOFF * off_screen);
+    let panning = 0.5 + 0.5 * PAN_WIDTH * screen.x.clamp(-1.0, 1.0);
+    (volume, panning)
+}
+
+pub fn play_event_sounds(
+    time: Res<Time>,
+    audio: Res<Audio>,
+    windows: Res<Windows>,
+    settings: Res<Settings>,
+    rig: Res<CameraRig>,
+    mut events: EventReader<SimEvent>,
+    mut game_audio: ResMut<GameAudio>,
+) {
+    let now = time.seconds_since_startup();
+    let view = windows.get_primary().map_or(Vec2::ZERO, |window| {
+        Vec2::new(window.width(), window.height())
+    });
+    for event in events.iter() {
+        if let Some(sound) = Sound::of(event) {
+            let (volume, panning) = positional(&rig, view, event.pos());
+            let volume = settings.effects() * volume;
+            game_audio.play(&audio, sound, volume, panning, now);
+        }
+    }
+}
+
+/// the track for the state of the game, `knocked_out` lost the last match.
+fn music_track(state: i32, simulation: &Simulation, knocked_out: Option<Fighter>) -> Track {
+    let tense = Fighter::ALL.into_iter().any(|fighter| {
+        let fighter = simulation.fighter(fighter);
+        fighter.control != Control::Dummy
+            && fighter.lives > 0
+            && fighter.lives as f32 <= fighter.role.lives() as f32 * TENSION_LIVES
+    });
+    match (state, knocked_out) {
+        (0, Some(loser)) => {
+            // losing to the ai is a defeat, between people somebody won
+            if simulation.fighter(loser.other()).control == Control::Human {
+                Track::Victory
+            } else {
+                Track::Defeat
+            }
+        }
+        (0, None) => Track::Title,
+        _ if tense => Track::Tension,
+        _ => Track::Fight,
+    }
+}
+
+/// crossfades to the music of the stage for what is going on.
+pub fn update_music(
+    time: Res<Time>,
+    audio: Res<Audio>,
+    server: Res<AssetServer>,
+    settings: Res<Settings>,
+    sim: Res<Sim>,
+    mut events: EventReader<SimEvent>,
+    game_state_query: Query<&GameState>,
+    mut game_audio: ResMut<GameAudio>,
+) {
+    let game_audio = &mut *game_audio;
+    let state = match game_state_query.iter().next() {
+        Some(game_state) => game_state.state,
+        None => return,
+    };
+    // the simulation starts over as soon as a match is won, replays end
+    // without a result
+    for event in events.iter() {
+        if let SimEvent::KnockOut { fighter, .. } = *event {
+            game_audio.knocked_out = Some(fighter).filter(|_| state != 3);
+        }
+    }
+    let playing = state == 1 && sim.simulation.winner().is_none();
+    if playing || state == 3 {
+        game_audio.knocked_out = None;
+    }
+    // pausing keeps whatever plays
+    if state != 2 {
+        let track = music_track(state, &sim.simulation, game_audio.knocked_out);
+        let music = sim.stage.music(track).map(str::to_string);
+        if music != game_audio.music {
+            game_audio.music_channel = 1 - game_audio.music_channel;
+            let channel = &game_audio.music_channels[game_audio.music_channel];
+            audio.stop_channel(channel);
+            audio.set_volume_in_channel(0.0, channel);
+            if let Some(path) = &music {
+                let source = server.load(path.as_str());
+                // the end of the match plays once
+                match track {
+                    Track::Victory | Track::Defeat => audio.play_in_channel(source, channel),
+                    _ => audio.play_looped_in_channel(source, channel),
+                };
+            }
+            game_audio.music = music;
+            game_audio.fade = 0.0;
+        }
+    }
+
+    if game_audio.fade >= 1.0 && !settings.is_changed() {
+        return;
+    }
+    let was_fading = game_audio.fade < 1.0;
+    game_audio.fade = (game_audio.fade + time.delta_seconds() / CROSSFADE).min(1.0);
+    let [new, old] = [game_audio.music_channel, 1 - game_audio.music_channel]
+        .map(|index| &game_audio.music_channels[index]);
+    audio.set_volume_in_channel(settings.music() * game_audio.fade, new);
+    audio.set_volume_in_channel(settings.music() * (1.0 - game_audio.fade), old);
+    if was_fading && game_audio.fade >= 1.0 {
+        audio.stop_channel(old);
+    }
+}
@@ -0,0 +1,163 @@
+//! The match camera.
+//!
+//! It looks at the point between the fighters and zooms out as they move
+//! apart, easing towards that instead of jumping, and never shows anything
+//! outside the camera bounds of the stage. Hits and shotgun blasts shake it.
+
+use bevy::math::const_vec2;
+use bevy::prelude::*;
+use bevy::render::camera::CameraPlugin;
+
+use crate::sim::{SimEvent, Simulation, Weapon};
+use crate::stage::Stage;
+use crate::{GameState, Sim};
+
+// room kept around the fighters, in world units
+const MARGIN: Vec2 = const_vec2!([300.0, 250.0]);
+// world units per pixel, the camera never gets closer than the original view
+const MIN_ZOOM: f32 = 1.0;
+// how quickly the camera catches up, per second
+const SMOOTHING: f32 = 3.0;
+const HIT_TRAUMA: f32 = 0.6;
+const SHOTGUN_TRAUMA: f32 = 0.4;
+// trauma lost per second
+const TRAUMA_DECAY: f32 = 1.5;
+// how far the view shakes at full trauma, in pixels
+const MAX_SHAKE: f32 = 20.0;
+
+#[derive(Default)]
+pub struct CameraRig {
+    pub center: Vec2,
+    /// world units per pixel, 0 before the first frame
+    pub zoom: f32,
+    /// from 0 to 1, the shake is its square
+    trauma: f32,
+    /// the tick framed last, a new match starts over at a lower one
+    tick: u64,
+}
+
+impl CameraRig {
+    /// eases towards framing the fighters of `sim` for `dt` seconds, `view`
+    /// is the window size in pixels. the camera jumps there at the start of
+    /// a match.
+    pub fn follow(&mut self, sim: &Simulation, stage: &Stage, view: Vec2, dt: f32) {
+        let fighters = [sim.fighters[0].pos, sim.fighters[1].pos];
+        let min = fighters[0].min(fighters[1]) - MARGIN;
+        let max = fighters[0].max(fighters[1]) + MARGIN;
+        let bounds = stage.camera_max - stage.camera_min;
+        // as close as the fighters allow, but never wider than the bounds
+        let zoom = ((max - min) / view)
+            .max_element()
+            .max(MIN_ZOOM)
+            .min((bounds / view).min_element());
+        let center = (min + max) / 2.0;
+
+        if self.zoom == 0.0 || sim.tick < self.tick {
+            self.center = center;
+            self.zoom = zoom;
+        } else {
+            let t = 1.0 - (-SMOOTHING * dt).exp();
+            self.center += (center - self.center) * t;
+            self.zoom += (zoom - self.zoom) * t;
+        }
+        self.tick = sim.tick;
+
+        let half = view * self.zoom / 2.0;
+        let (min, max) = (stage.camera_min + half, stage.camera_max - half);
+        self.center = Vec2::new(
+            clamp_axis(self.center.x, min.x, max.x),
+            clamp_axis(self.center.y, min.y, max.y),
+        );
+        self.trauma = (self.trauma - TRAUMA_DECAY * dt).max(0.0);
+    }
+
+    pub fn shake<'a>(&mut self, events: impl Iterator<Item = &'a SimEvent>) {
+        for event in events {
+            self.trauma += match event {
+                SimEvent::Hit { .. } => HIT_TRAUMA,
+                SimEvent::Shot {
+                    weapon: Weapon::Shotgun,
+                    ..
+                } => SHOTGUN_TRAUMA,
+                _ => 0.0,
+            };
+        }
+        self.trauma = self.trauma.min(1.0);
+    }
+
+    /// where the camera is this frame, with the shake.
+    pub fn shaken_center(&self) -> Vec2 {
+        let shake = self.trauma * self.trauma * MAX_SHAKE * self.zoom;
+        let offset = Vec2::new(rand::random::<f32>(), rand::random::<f32>()) * 2.0 - Vec2::ONE;
+        self.center + offset * shake
+    }
+}
+
+/// keeps `value` between `min` and `max`, or in the middle of them if the
+/// view is larger than the bounds.
+fn clamp_axis(value: f32, min: f32, max: f32) -> f32 {
+    if min > max {
+        (min + max) / 2.0
+    } else {
+        value.clamp(min, max)
+    }
+}
+
+/// the world position shown at `pos`, a position in logical pixels from the
+/// bottom left of `window` like `Window::cursor_position`. goes through the
+/// projection of the camera, so it holds up with any window size, scale
+/// factor, camera position and zoom.
+pub fn screen_to_world(window: &Window, camera: &Camera, transform: &Transform, pos: Vec2) -> Vec2 {
+    let size = Vec2::new(window.width(), window.height());
+    let ndc = pos / size * 2.0 - Vec2::ONE;
+    let world = transform.compute_matrix() * camera.projection_matrix.inverse();
+    world.project_point3(ndc.extend(0.0)).truncate()
+}
+
+/// where the cursor points to in the world, if it is over the window of the
+/// 2d camera.
+pub fn cursor_to_world<'a>(
+    windows: &Windows,
+    mut cameras: impl Iterator<Item = (&'a Camera, &'a Transform)>,
+) -> Option<Vec2> {
+    let (camera, transform) =
+        cameras.find(|(camera, _)| camera.name.as_deref() == Some(CameraPlugin::CAMERA_2D))?;
+    let window = windows.get(camera.window)?;
+    let pos = window.cursor_position()?;
+    Some(screen_to_world(window, camera, transform, pos))
+}
+
+pub fn update_camera(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    sim: Res<Sim>,
+    mut events: EventReader<SimEvent>,
+    mut rig: ResMut<CameraRig>,
+    game_state_query: Query<&GameState>,
+    mut camera_query: Query<(&Camera, &mut Transform)>,
+) {
+    rig.shake(events.iter());
+    // the stage editor moves the camera itself
+    if game_state_query
+        .iter()
+        .any(|game_state| game_state.state == 4)
+    {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let view = Vec2::new(window.width(), window.height());
+    rig.follow(&sim.simulation, &sim.stage, view, time.delta_seconds());
+
+    let center = rig.shaken_center();
+    for (camera, mut transform) in camera_query.iter_mut() {
+        if camera.name.as_deref() != Some(CameraPlugin::CAMERA_2D) {
+            continue;
+        }
+        transform.translation.x = center.x;
+        transform.translation.y = center.y;
+        transform.scale = Vec3::new(rig.zoom, rig.zoom, 1.0);
+    }
+}
@@ -0,0 +1,153 @@
+//! In-game menu to rebind the controls, opened with `C` on the title screen.
+//!
+//! Up and down select an action, return waits for the next key, mouse or
+//! gamepad button to bind it, backspace removes the keyboard and mouse
+//! bindings and escape closes the menu. On a gamepad the d-pad selects, the
+//! south button rebinds and the east button closes the menu.
+
+use bevy::prelude::*;
+
+use crate::gamepad::{self, GamepadLobby};
+use crate::input_map::{Action, Binding, InputMap};
+use crate::sim::Fighter;
+use crate::GameState;
+
+const ROWS: usize = 2 * Action::ALL.len();
+
+#[derive(Default)]
+pub struct ControlsMenu {
+    pub open: bool,
+    selected: usize,
+    waiting: bool,
+}
+
+#[derive(Component)]
+pub struct ControlsText;
+
+fn row(index: usize) -> (Fighter, Action) {
+    let actions = Action::ALL.len();
+    (Fighter::ALL[index / actions], Action::ALL[index % actions])
+}
+
+pub fn setup_controls_menu(mut commands: Commands) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(80.0),
+                    left: Val::Px(20.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(ControlsText);
+}
+
+pub fn update_controls_menu(
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_button: Res<Input<MouseButton>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    lobby: Res<GamepadLobby>,
+    asset_server: Res<AssetServer>,
+    game_state_query: Query<&GameState>,
+    mut menu: ResMut<ControlsMenu>,
+    mut input_map: ResMut<InputMap>,
+    mut text_query: Query<&mut Text, With<ControlsText>>,
+) {
+    let on_title = game_state_query
+        .iter()
+        .any(|game_state| game_state.state == 0);
+    let (fighter, action) = row(menu.selected);
+    let pressed = |key: KeyCode, button: GamepadButtonType| {
+        keyboard_input.just_pressed(key) || gamepad::just_pressed(&lobby, &gamepad_buttons, button)
+    };
+
+    if !menu.open {
+        if on_title && keyboard_input.just_pressed(KeyCode::C) {
+            menu.open = true;
+        }
+    } else if menu.waiting {
+        if keyboard_input.just_pressed(KeyCode::Escape) {
+            menu.waiting = false;
+        } else if let Some(binding) =
+            Binding::just_pressed(&keyboard_input, &mouse_button, &gamepad_buttons)
+        {
+            input_map.bind(fighter, action, binding);
+            menu.waiting = false;
+        }
+    } else if pressed(KeyCode::Escape, GamepadButtonType::East) {
+        menu.open = false;
+    } else if pressed(KeyCode::Up, GamepadButtonType::DPadUp) {
+        menu.selected = (menu.selected + ROWS - 1) % ROWS;
+    } else if pressed(KeyCode::Down, GamepadButtonType::DPadDown) {
+        menu.selected = (menu.selected + 1) % ROWS;
+    } else if pressed(KeyCode::Return, GamepadButtonType::South) {
+        menu.waiting = true;
+    } else if keyboard_input.just_pressed(KeyCode::Back) {
+        input_map.clear(fighter, action);
+    }
+
+    if !menu.is_changed() && !input_map.is_changed() {
+        return;
+    }
+    for mut text in text_query.iter_mut() {
+        text.sections = if menu.open {
+            menu_sections(&menu, &input_map, asset_server.load("fonts/IceCaps.ttf"))
+        } else {
+            Vec::new()
+        };
+    }
+}
+
+fn menu_sections(
+    menu: &ControlsMenu,
+    input_map: &InputMap,
+    font: Handle<Font>,
+) -> Vec<TextSection> {
+    let line = |value: String, color: Color| TextSection {
+        value,
+        style: TextStyle {
+            // small enough for a row per action of both players in a 720
+            // pixel high window
+            font_size: 26.0,
+            font: font.clone(),
+            color,
+        },
+    };
+
+    let mut sections = vec![line(
+        "Controls: up/down select, return or south rebind, backspace clear, escape or east save\n"
+            .to_string(),
+        Color::WHITE,
+    )];
+    for index in 0..ROWS {
+        let (fighter, action) = row(index);
+        let player = match fighter {
+            Fighter::Player => "Player 1",
+            Fighter::Opponent => "Player 2",
+        };
+        let bindings = if menu.waiting && index == menu.selected {
+            "press a key or button...".to_string()
+        } else {
+            input_map
+                .bindings(fighter, action)
+                .iter()
+                .map(Binding::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let color = if index == menu.selected {
+            Color::GOLD
+        } else {
+            Color::WHITE
+        };
+        sections.push(line(
+            format!("{}  {}: {}\n", player, action.label(), bindings),
+            color,
+        ));
+    }
+    sections
+}
@@ -0,0 +1,486 @@
+//! The stage editor, `E` on the title screen or `duel --edit <stage>`.
+//!
+//! Dragging on empty space draws a new platform, dragging a platform moves it
+//! and dragging an edge of the selected platform resizes it. `Delete` removes
+//! the selected platform and `O` switches it between solid and one-way. `1`
+//! and `2` move the player and opponent spawn to the cursor. The mouse wheel
+//! zooms and the right mouse button pans. The match camera stays inside the
+//! white frame, fighters leaving the red one respawn.
+//!
+//! `Ctrl+Z` undoes, `Ctrl+Y` redoes, `Ctrl+S` writes the stage file and
+//! escape goes back to the title screen with the edited stage.
+
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+use bevy::render::camera::CameraPlugin;
+
+use crate::camera;
+use crate::controls_menu::ControlsMenu;
+use crate::sim::{Motion, Platform, Simulation};
+use crate::stage::Stage;
+use crate::{GameState, Sim, BODY_SIZE};
+
+// positions and sizes snap to this
+const GRID: f32 = 10.0;
+// how close to an edge of the selected platform a drag resizes it, in pixels
+const HANDLE: f32 = 8.0;
+const MAX_UNDO: usize = 100;
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 8.0;
+
+const HELP: &str = "drag to draw, move or resize platforms, delete removes, o one-way\n\
+                    1/2 spawns, wheel zoom, right drag pan, ctrl z/y undo/redo, ctrl s save, \
+                    escape exit";
+
+#[derive(Clone, Copy)]
+enum Drag {
+    /// a new platform from this corner to the cursor
+    Create { from: Vec2 },
+    /// the selected platform, keeping it at the same spot under the cursor
+    Move { offset: Vec2 },
+    /// the edges of the selected platform that follow the cursor, -1 for
+    /// left or bottom and 1 for right or top
+    Resize { edges: Vec2 },
+    /// the camera, from this cursor position in the window
+    Pan { from: Vec2 },
+}
+
+#[derive(Default)]
+pub struct StageEditor {
+    stage: Option<Stage>,
+    /// the stage before every change
+    undo: Vec<Stage>,
+    redo: Vec<Stage>,
+    /// the stage as it was saved or loaded
+    saved: Option<Stage>,
+    /// index into the platforms
+    selected: Option<usize>,
+    drag: Option<Drag>,
+    /// the stage before the current drag
+    drag_start: Option<Stage>,
+    /// in world coordinates, snapped to the grid
+    cursor: Vec2,
+    message: String,
+}
+
+impl StageEditor {
+    /// starts editing `stage` the next frame.
+    pub fn open(&mut self, stage: Stage) {
+        *self = StageEditor {
+            saved: Some(stage.clone()),
+            stage: Some(stage),
+            ..Default::default()
+        };
+    }
+
+    /// keeps `before` for undo if the stage changed since.
+    fn commit(&mut self, before: Stage) {
+        if self.stage.as_ref() == Some(&before) {
+            return;
+        }
+        self.undo.push(before);
+        if self.undo.len() > MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    fn undo(&mut self) {
+        if let Some(previous) = self.undo.pop() {
+            self.redo.extend(self.stage.replace(previous));
+            self.selected = None;
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(next) = self.redo.pop() {
+            self.undo.extend(self.stage.replace(next));
+            self.selected = None;
+        }
+    }
+
+    fn selected_platform(&mut self) -> Option<&mut Platform> {
+        let selected = self.selected?;
+        self.stage.as_mut()?.arena.platforms.get_mut(selected)
+    }
+
+    /// starts a drag at the cursor, `zoom` is the size of a pixel.
+    fn press(&mut self, zoom: f32) {
+        let stage = match &self.stage {
+            Some(stage) => stage,
+            None => return,
+        };
+        let platforms = &stage.arena.platforms;
+        let edges = self
+            .selected
+            .and_then(|selected| platforms.get(selected))
+            .map_or(Vec2::ZERO, |platform| {
+                edges_at(platform, self.cursor, HANDLE * zoom)
+            });
+        // the last platform is drawn on top
+        let hovered = platforms
+            .iter()
+            .rposition(|platform| contains(platform, self.cursor));
+
+        self.drag_start = Some(stage.clone());
+        self.drag = Some(if edges != Vec2::ZERO {
+            Drag::Resize { edges }
+        } else if let Some(hovered) = hovered {
+            self.selected = Some(hovered);
+            Drag::Move {
+                offset: platforms[hovered].pos - self.cursor,
+            }
+        } else {
+            self.selected = None;
+            Drag::Create { from: self.cursor }
+        });
+    }
+
+    /// follows the cursor with the dragged platform.
+    fn drag(&mut self) {
+        let cursor = self.cursor;
+        match self.drag {
+            Some(Drag::Move { offset }) => {
+                if let Some(platform) = self.selected_platform() {
+                    platform.pos = snap(cursor + offset);
+                }
+            }
+            Some(Drag::Resize { edges }) => {
+                if let Some(platform) = self.selected_platform() {
+                    let mut min = platform.pos - platform.size / 2.0;
+                    let mut max = platform.pos + platform.size / 2.0;
+                    if edges.x > 0.0 {
+                        max.x = cursor.x.max(min.x + GRID);
+                    } else if edges.x < 0.0 {
+                        min.x = cursor.x.min(max.x - GRID);
+                    }
+                    if edges.y > 0.0 {
+                        max.y = cursor.y.max(min.y + GRID);
+                    } else if edges.y < 0.0 {
+                        min.y = cursor.y.min(max.y - GRID);
+                    }
+                    platform.pos = (min + max) / 2.0;
+                    platform.size = max - min;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn release(&mut self) {
+        if let (Some(Drag::Create { from }), Some(stage)) = (self.drag, &mut self.stage) {
+            let (min, max) = (from.min(self.cursor), from.max(self.cursor));
+            if max.x - min.x >= GRID && max.y - min.y >= GRID {
+                stage.arena.platforms.push(Platform {
+                    pos: (min + max) / 2.0,
+                    size: max - min,
+                    one_way: false,
+                    motion: Motion::Static,
+                });
+                self.selected = Some(stage.arena.platforms.len() - 1);
+            }
+        }
+        self.drag = None;
+        if let Some(before) = self.drag_start.take() {
+            self.commit(before);
+        }
+    }
+
+    /// the rectangles to outline as centre, size and color.
+    fn outlines(&self) -> Vec<(Vec2, Vec2, Color)> {
+        let stage = match &self.stage {
+            Some(stage) => stage,
+            None => return Vec::new(),
+        };
+        let arena = &stage.arena;
+        let mut outlines = vec![
+            (
+                (arena.blast_min + arena.blast_max) / 2.0,
+                arena.blast_max - arena.blast_min,
+                Color::RED,
+            ),
+            (arena.spawns[0], BODY_SIZE, Color::GREEN),
+            (arena.spawns[1], BODY_SIZE, Color::ORANGE),
+            (
+                (stage.camera_min + stage.camera_max) / 2.0,
+                stage.camera_max - stage.camera_min,
+                Color::WHITE,
+            ),
+        ];
+        if let Some(platform) = self.selected.and_then(|i| arena.platforms.get(i)) {
+            outlines.push((platform.pos, platform.size, Color::YELLOW));
+        }
+        if let Some(Drag::Create { from }) = self.drag {
+            let (min, max) = (from.min(self.cursor), from.max(self.cursor));
+            outlines.push(((min + max) / 2.0, max - min, Color::YELLOW));
+        }
+        outlines
+    }
+}
+
+fn snap(pos: Vec2) -> Vec2 {
+    (pos / GRID).round() * GRID
+}
+
+fn contains(platform: &Platform, pos: Vec2) -> bool {
+    let distance = (pos - platform.pos).abs();
+    distance.x <= platform.size.x / 2.0 && distance.y <= platform.size.y / 2.0
+}
+
+/// the edges of `platform` within `reach` of `pos`, top and right win over
+/// bottom and left.
+fn edges_at(platform: &Platform, pos: Vec2, reach: f32) -> Vec2 {
+    let half = platform.size / 2.0;
+    let offset = pos - platform.pos;
+    if offset.x.abs() > half.x + reach || offset.y.abs() > half.y + reach {
+        return Vec2::ZERO;
+    }
+    let edge = |offset: f32, half: f32| {
+        if (offset - half).abs() <= reach {
+            1.0
+        } else if (offset + half).abs() <= reach {
+            -1.0
+        } else {
+            0.0
+        }
+    };
+    Vec2::new(edge(offset.x, half.x), edge(offset.y, half.y))
+}
+
+#[derive(Component)]
+pub struct EditorText;
+
+#[derive(Component)]
+pub struct EditorSprite;
+
+pub fn setup_stage_editor(mut commands: Commands) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(80.0),
+                    left: Val::Px(20.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(EditorText);
+}
+
+pub fn update_stage_editor(
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_button: Res<Input<MouseButton>>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    windows: Res<Windows>,
+    menu: Res<ControlsMenu>,
+    asset_server: Res<AssetServer>,
+    mut editor: ResMut<StageEditor>,
+    mut sim: ResMut<Sim>,
+    mut game_state_query: Query<&mut GameState>,
+    mut camera_query: Query<(&Camera, &mut Transform)>,
+    mut text_query: Query<&mut Text, With<EditorText>>,
+) {
+    if sim.online {
+        return;
+    }
+    let editor = &mut *editor;
+    let scroll: f32 = mouse_wheel.iter().map(|event| event.y).sum();
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let (projection, mut camera) = match camera_query
+        .iter_mut()
+        .find(|(camera, _)| camera.name.as_deref() == Some(CameraPlugin::CAMERA_2D))
+    {
+        Some(camera) => camera,
+        None => return,
+    };
+
+    for mut game_state in game_state_query.iter_mut() {
+        if game_state.state == 0 && !menu.open && keyboard_input.just_pressed(KeyCode::E) {
+            editor.open(sim.stage.clone());
+        }
+        if game_state.state != 4 {
+            if editor.stage.is_none() {
+                continue;
+            }
+            game_state.state = 4;
+        }
+
+        if keyboard_input.just_pressed(KeyCode::Escape) {
+            *editor = StageEditor::default();
+            game_state.state = 0;
+            for mut text in text_query.iter_mut() {
+                text.sections = Vec::new();
+            }
+            continue;
+        }
+
+        let zoom = camera.scale.x;
+        if scroll != 0.0 {
+            camera.scale = Vec3::splat((zoom * 0.9f32.powf(scroll)).clamp(MIN_ZOOM, MAX_ZOOM));
+        }
+        if let Some(pos) = window.cursor_position() {
+            editor.cursor = snap(camera::screen_to_world(window, projection, &camera, pos));
+            if mouse_button.just_pressed(MouseButton::Right) {
+                editor.drag = Some(Drag::Pan { from: pos });
+            } else if mouse_button.just_pressed(MouseButton::Left) {
+                editor.press(zoom);
+            }
+            if let Some(Drag::Pan { from }) = editor.drag {
+                camera.translation -= ((pos - from) * zoom).extend(0.0);
+                editor.drag = Some(Drag::Pan { from: pos });
+            }
+        }
+        if mouse_button.just_released(MouseButton::Right)
+            || mouse_button.just_released(MouseButton::Left)
+        {
+            editor.release();
+        } else {
+            editor.drag();
+        }
+
+        let ctrl =
+            keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+        let shift =
+            keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+        if ctrl && keyboard_input.just_pressed(KeyCode::Z) {
+            if shift {
+                editor.redo();
+            } else {
+                editor.undo();
+            }
+        } else if ctrl && keyboard_input.just_pressed(KeyCode::Y) {
+            editor.redo();
+        } else if ctrl && keyboard_input.just_pressed(KeyCode::S) {
+            if let Some(stage) = editor.stage.clone() {
+                editor.message = match stage.save() {
+                    Ok(()) => {
+                        let message = format!("saved {}.stage", stage.id);
+                        editor.saved = Some(stage);
+                        message
+                    }
+                    Err(err) => format!("could not save: {}", err),
+                };
+            }
+        } else if let Some(before) = editor.stage.clone() {
+            let cursor = editor.cursor;
+            if keyboard_input.just_pressed(KeyCode::Delete)
+                || keyboard_input.just_pressed(KeyCode::Back)
+            {
+                if let (Some(selected), Some(stage)) = (editor.selected.take(), &mut editor.stage) {
+                    stage.arena.platforms.remove(selected);
+                }
+            } else if keyboard_input.just_pressed(KeyCode::O) {
+                if let Some(platform) = editor.selected_platform() {
+                    platform.one_way = !platform.one_way;
+                }
+            } else if keyboard_input.just_pressed(KeyCode::Key1) {
+                if let Some(stage) = &mut editor.stage {
+                    stage.arena.spawns[0] = cursor;
+                }
+            } else if keyboard_input.just_pressed(KeyCode::Key2) {
+                if let Some(stage) = &mut editor.stage {
+                    stage.arena.spawns[1] = cursor;
+                }
+            }
+            // drags are committed once they end
+            if editor.drag.is_none() {
+                editor.commit(before);
+            }
+        }
+
+        // the fighters stand on the spawns of the edited stage
+        if let Some(stage) = &editor.stage {
+            if sim.stage != *stage {
+                let settings = sim.simulation.settings;
+                sim.simulation =
+                    Simulation::with_arena(rand::random(), settings, stage.arena.clone());
+                sim.stage = stage.clone();
+            }
+        }
+
+        let font = asset_server.load("fonts/IceCaps.ttf");
+        let line = |value: String| TextSection {
+            value,
+            style: TextStyle {
+                font_size: 30.0,
+                font: font.clone(),
+                color: Color::WHITE,
+            },
+        };
+        let status = format!(
+            "Editing {}{}  {}\n",
+            sim.stage.id,
+            if editor.saved.as_ref() == Some(&sim.stage) {
+                ""
+            } else {
+                " (unsaved)"
+            },
+            editor.message
+        );
+        for mut text in text_query.iter_mut() {
+            text.sections = vec![line(status.clone()), line(HELP.to_string())];
+        }
+    }
+}
+
+/// outlines the blast zone, the camera bounds, the spawns and the selected
+/// platform while editing.
+pub fn update_editor_overlay(
+    mut commands: Commands,
+    editor: Res<StageEditor>,
+    camera_query: Query<(&Camera, &Transform)>,
+    overlay_query: Query<Entity, With<EditorSprite>>,
+    mut shown: Local<Vec<(Vec2, Vec2, Color)>>,
+) {
+    let zoom = camera_query
+        .iter()
+        .find(|(camera, _)| camera.name.as_deref() == Some(CameraPlugin::CAMERA_2D))
+        .map_or(1.0, |(_, transform)| transform.scale.x);
+
+    // two pixels wide at any zoom
+    let width = 2.0 * zoom;
+    let mut lines = Vec::new();
+    for (pos, size, color) in editor.outlines() {
+        let (half, horizontal, vertical) = (
+            size / 2.0,
+            Vec2::new(size.x + width, width),
+            Vec2::new(width, size.y + width),
+        );
+        lines.extend([
+            (pos + Vec2::new(0.0, half.y), horizontal, color),
+            (pos - Vec2::new(0.0, half.y), horizontal, color),
+            (pos + Vec2::new(half.x, 0.0), vertical, color),
+            (pos - Vec2::new(half.x, 0.0), vertical, color),
+        ]);
+    }
+    if *shown == lines {
+        return;
+    }
+
+    for entity in overlay_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    for (pos, size, color) in lines.iter() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                transform: Transform {
+                    translation: pos.extend(5.0),
+                    scale: size.extend(0.0),
+                    ..Default::default()
+                },
+                sprite: Sprite {
+                    color: *color,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(EditorSprite);
+    }
+    *shown = lines;
+}
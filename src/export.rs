@@ -0,0 +1,414 @@
+//! Renders a replay to png frames or an animated gif without a window.
+//!
+//! `duel --export <replay> <output>` writes `frame-00000.png`,
+//! `frame-00001.png`, ... into the `output` directory, or a single animated
+//! gif when `output` ends in `.gif`. `--every <ticks>` picks how many ticks
+//! pass between frames (default 2, so 30 frames per second), `--start` and
+//! `--end` cut a clip out of the match in seconds and `--scale` shrinks or
+//! grows the 1280x720 frames.
+//!
+//! The frames are drawn on the cpu with the same images, animation frames,
+//! sizes and colors as the sprites in the game, framed by the same camera
+//! without the shake. Text and particles are left out.
+
+use bevy::math::{Mat2, Vec2};
+use image::codecs::gif::GifEncoder;
+use image::imageops;
+use image::{Delay, Frame, Rgba, RgbaImage};
+
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use crate::animation::{Animation, AnimationSheet, Clip};
+use crate::camera::CameraRig;
+use crate::replay::Replay;
+use crate::sim::{self, Fighter, HazardKind, Role, Simulation, WeaponState};
+use crate::stage::Stage;
+use crate::{BODY_SIZE, BULLET_SIZE, GLOVES_SIZE, WEAPON_SIZE};
+
+const WIDTH: f32 = 1280.0;
+const HEIGHT: f32 = 720.0;
+// trades color quantization quality for encoding time, from 1 to 30
+const GIF_SPEED: i32 = 10;
+
+const PLATFORM: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const ONE_WAY_PLATFORM: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
+const CRUMBLING_PLATFORM: [f32; 4] = [0.55, 0.45, 0.35, 1.0];
+const SPIKES: [f32; 4] = [0.45, 0.4, 0.45, 1.0];
+const LAVA: [f32; 4] = [1.0, 0.35, 0.05, 1.0];
+const LASER: [f32; 4] = [1.0, 0.1, 0.1, 0.9];
+const LASER_WARNING: [f32; 4] = [1.0, 0.1, 0.1, 0.25];
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const KO: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+const HIT: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+const BLOCKING: [f32; 4] = [0.6, 0.6, 1.0, 1.0];
+const LIFE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+pub struct ExportOptions {
+    /// ticks between two frames
+    pub every: usize,
+    pub start_tick: usize,
+    pub end_tick: usize,
+    pub scale: f32,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            every: 2,
+            start_tick: 0,
+            end_tick: usize::MAX,
+            scale: 1.0,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// reads `--every`, `--start`, `--end` and `--scale` from the command
+    /// line.
+    pub fn from_args(args: &[String]) -> Self {
+        let arg = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+                .and_then(|value| value.parse::<f32>().ok())
+        };
+        let seconds = |value: f32| (value * sim::TICK_RATE as f32) as usize;
+        let defaults = ExportOptions::default();
+        ExportOptions {
+            every: arg("--every").map_or(defaults.every, |every| (every as usize).max(1)),
+            start_tick: arg("--start").map_or(defaults.start_tick, seconds),
+            end_tick: arg("--end").map_or(defaults.end_tick, seconds),
+            scale: arg("--scale").unwrap_or(defaults.scale).max(0.01),
+        }
+    }
+}
+
+struct Images {
+    /// the animation sheet of each role and its image, by `Role`
+    bodies: [(AnimationSheet, RgbaImage); 2],
+    gloves: RgbaImage,
+    gun: RgbaImage,
+    shotgun: RgbaImage,
+    bullet: RgbaImage,
+    /// the images of the stage layers, in order
+    layers: Vec<RgbaImage>,
+}
+
+impl Images {
+    fn load(stage: &Stage) -> io::Result<Self> {
+        let load = |name: &str| {
+            image::open(Path::new("assets").join(name))
+                .map(|image| image.to_rgba8())
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+        };
+        let body = |role| {
+            let sheet = AnimationSheet::load_or_still(role);
+            load(&sheet.image).map(|image| (sheet, image))
+        };
+        Ok(Images {
+            bodies: [body(Role::Boxer)?, body(Role::WeaponSpecialist)?],
+            gloves: load("boxing_gloves.png")?,
+            gun: load("gun.png")?,
+            shotgun: load("shotgun.png")?,
+            bullet: load("bullet.png")?,
+            layers: stage
+                .layers
+                .iter()
+                .map(|layer| load(&layer.image))
+                .collect::<io::Result<_>>()?,
+        })
+    }
+
+    /// the frame of the sheet of `role` that `animation` shows.
+    fn body(&self, role: Role, animation: &Animation) -> RgbaImage {
+        let (sheet, image) = match role {
+            Role::Boxer => &self.bodies[0],
+            Role::WeaponSpecialist => &self.bodies[1],
+        };
+        let frame = animation.frame(sheet);
+        let (width, height) = (sheet.frame_size.x as u32, sheet.frame_size.y as u32);
+        let x = (frame % sheet.columns) as u32 * width;
+        let y = (frame / sheet.columns) as u32 * height;
+        imageops::crop_imm(image, x, y, width, height).to_image()
+    }
+}
+
+pub fn run(replay_path: &Path, output: &Path, options: &ExportOptions) -> io::Result<()> {
+    let replay = Replay::load(replay_path)?;
+    let images = Images::load(&replay.stage)?;
+    let gif = output
+        .extension()
+        .map_or(false, |extension| extension == "gif");
+
+    let mut frames = Vec::new();
+    let mut sim = replay.simulation();
+    let end_tick = options.end_tick.min(replay.inputs.len());
+    let mut camera = CameraRig::default();
+    // played every tick, so no clip is missed between two frames
+    let mut animations = Fighter::ALL.map(|_| Animation::default());
+    let mut index = 0;
+    loop {
+        let tick = sim.tick as usize;
+        for fighter in Fighter::ALL {
+            let clip = Clip::of(sim.fighter(fighter));
+            animations[fighter.index()].advance(clip, sim::DT);
+        }
+        if tick >= options.start_tick && (tick - options.start_tick) % options.every == 0 {
+            let view = Vec2::new(WIDTH, HEIGHT);
+            let dt = options.every as f32 * sim::DT;
+            camera.follow(&sim, &replay.stage, view, dt);
+            let frame = render(
+                &sim,
+                &replay.stage,
+                &images,
+                &animations,
+                &camera,
+                options.scale,
+            );
+            if gif {
+                frames.push(frame);
+            } else {
+                fs::create_dir_all(output)?;
+                frame
+                    .save(output.join(format!("frame-{:05}.png", index)))
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+            }
+            index += 1;
+        }
+        if tick >= end_tick {
+            break;
+        }
+        sim.step(&replay.inputs[tick]);
+    }
+
+    if gif {
+        let millis = (options.every as u32 * 1000) / sim::TICK_RATE;
+        let mut encoder = GifEncoder::new_with_speed(File::create(output)?, GIF_SPEED);
+        encoder
+            .encode_frames(
+                frames.into_iter().map(|frame| {
+                    Frame::from_parts(frame, 0, 0, Delay::from_numer_denom_ms(millis, 1))
+                }),
+            )
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+    }
+    println!("exported {} frames to {}", index, output.display());
+    Ok(())
+}
+
+fn render(
+    sim: &Simulation,
+    stage: &Stage,
+    images: &Images,
+    animations: &[Animation; 2],
+    camera: &CameraRig,
+    scale: f32,
+) -> RgbaImage {
+    let mut canvas = Canvas {
+        image: RgbaImage::new((WIDTH * scale) as u32, (HEIGHT * scale) as u32),
+        center: camera.center,
+        scale: scale / camera.zoom,
+    };
+    let [r, g, b] = stage.background;
+    canvas.fill([r, g, b, 1.0]);
+    for (layer, image) in stage.layers.iter().zip(images.layers.iter()) {
+        let pos = layer.pos + camera.center * layer.parallax;
+        canvas.sprite(image, pos, layer.size, 0.0, WHITE);
+    }
+    for (platform, state) in sim.arena.platforms.iter().zip(sim.platforms.iter()) {
+        let color = if state.is_crumbling() {
+            CRUMBLING_PLATFORM
+        } else if platform.one_way {
+            ONE_WAY_PLATFORM
+        } else {
+            PLATFORM
+        };
+        canvas.rect(state.pos, platform.size, color);
+    }
+    for hazard in sim.arena.hazards.iter() {
+        let color = match hazard.kind {
+            HazardKind::Spikes => SPIKES,
+            HazardKind::Lava => LAVA,
+            HazardKind::Laser { .. } => continue,
+        };
+        canvas.rect(hazard.pos, hazard.size, color);
+    }
+
+    for fighter in Fighter::ALL {
+        let state = sim.fighter(fighter);
+        let body = images.body(state.role, &animations[fighter.index()]);
+        let tint = if state.lives == 0 {
+            KO
+        } else if state.is_hit() {
+            HIT
+        } else if state.blocking {
+            BLOCKING
+        } else {
+            WHITE
+        };
+        let mirror = Vec2::new(state.facing, 1.0);
+        canvas.sprite(&body, state.pos, BODY_SIZE * mirror, 0.0, tint);
+    }
+    for fighter in Fighter::ALL {
+        let state = sim.fighter(fighter);
+        if state.role == Role::Boxer {
+            let size = GLOVES_SIZE * Vec2::new(state.facing, 1.0);
+            canvas.sprite(&images.gloves, state.gloves.pos, size, 0.0, WHITE);
+        }
+        let weapons: [(&WeaponState, &RgbaImage); 2] =
+            [(&state.gun, &images.gun), (&state.shotgun, &images.shotgun)];
+        for (weapon, image) in weapons {
+            // upright when turned around, like the sprites
+            let size = WEAPON_SIZE * Vec2::new(1.0, -state.facing);
+            if weapon.is_active {
+                canvas.sprite(image, weapon.pos, size, weapon.angle, WHITE);
+            }
+        }
+    }
+    for bullet in sim.bullets.iter() {
+        canvas.sprite(&images.bullet, bullet.pos, BULLET_SIZE, 0.0, WHITE);
+    }
+    // lasers go over the fighters
+    for hazard in sim.arena.hazards.iter() {
+        if let HazardKind::Laser { .. } = hazard.kind {
+            match hazard.pos_at(sim.tick) {
+                Some(pos) => canvas.rect(pos, hazard.size, LASER),
+                None if hazard.is_warning(sim.tick) => {
+                    canvas.rect(hazard.pos, hazard.size, LASER_WARNING)
+                }
+                None => (),
+            }
+        }
+    }
+
+    // lives as pips in the top corners, in place of the text
+    canvas.center = Vec2::ZERO;
+    canvas.scale = scale;
+    for fighter in Fighter::ALL {
+        let lives = sim.fighter(fighter).lives;
+        for life in 0..lives {
+            let x = 20.0 + life as f32 * 16.0;
+            let x = match fighter {
+                Fighter::Player => -WIDTH / 2.0 + x,
+                Fighter::Opponent => WIDTH / 2.0 - x,
+            };
+            canvas.rect(
+                Vec2::new(x, HEIGHT / 2.0 - 20.0),
+                Vec2::new(10.0, 10.0),
+                LIFE,
+            );
+        }
+    }
+    canvas.image
+}
+
+/// an image looking at the world through the 2d camera.
+struct Canvas {
+    image: RgbaImage,
+    /// the world position in the middle of the image
+    center: Vec2,
+    /// pixels per world unit
+    scale: f32,
+}
+
+impl Canvas {
+    fn to_pixel(&self, pos: Vec2) -> Vec2 {
+        Vec2::new(
+            self.image.width() as f32 / 2.0 + (pos.x - self.center.x) * self.scale,
+            self.image.height() as f32 / 2.0 - (pos.y - self.center.y) * self.scale,
+        )
+    }
+
+    fn fill(&mut self, color: [f32; 4]) {
+        let color = to_rgba(color);
+        for pixel in self.image.pixels_mut() {
+            *pixel = color;
+        }
+    }
+
+    fn rect(&mut self, center: Vec2, size: Vec2, color: [f32; 4]) {
+        let min = self.to_pixel(center + Vec2::new(-size.x, size.y) / 2.0);
+        let max = self.to_pixel(center + Vec2::new(size.x, -size.y) / 2.0);
+        for y in self.rows(min.y, max.y) {
+            for x in self.columns(min.x, max.x) {
+                blend(self.image.get_pixel_mut(x, y), color);
+            }
+        }
+    }
+
+    /// draws `image` stretched to `size`, rotated by `angle` around its
+    /// centre and multiplied with `tint`, like a bevy sprite. a negative
+    /// width or height mirrors it.
+    fn sprite(&mut self, image: &RgbaImage, center: Vec2, size: Vec2, angle: f32, tint: [f32; 4]) {
+        let rotation = Mat2::from_angle(angle);
+        let inverse = Mat2::from_angle(-angle);
+        let corners = [
+            Vec2::new(-0.5, -0.5),
+            Vec2::new(0.5, -0.5),
+            Vec2::new(-0.5, 0.5),
+            Vec2::new(0.5, 0.5),
+        ]
+        .map(|corner| self.to_pixel(center + rotation * (corner * size)));
+        let min = corners
+            .iter()
+            .fold(corners[0], |min, corner| min.min(*corner));
+        let max = corners
+            .iter()
+            .fold(corners[0], |max, corner| max.max(*corner));
+
+        for y in self.rows(min.y, max.y) {
+            for x in self.columns(min.x, max.x) {
+                let pixel = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let world = self.center
+                    + Vec2::new(
+                        (pixel.x - self.image.width() as f32 / 2.0) / self.scale,
+                        (self.image.height() as f32 / 2.0 - pixel.y) / self.scale,
+                    );
+                // from -0.5 to 0.5 across the sprite, y up
+                let local = (inverse * (world - center)) / size;
+                if local.x.abs() > 0.5 || local.y.abs() > 0.5 {
+                    continue;
+                }
+                let u = ((local.x + 0.5) * image.width() as f32) as u32;
+                let v = ((0.5 - local.y) * image.height() as f32) as u32;
+                let texel = image.get_pixel(u.min(image.width() - 1), v.min(image.height() - 1));
+                let color = [0, 1, 2, 3].map(|i| texel[i] as f32 / 255.0 * tint[i]);
+                blend(self.image.get_pixel_mut(x, y), color);
+            }
+        }
+    }
+
+    fn rows(&self, from: f32, to: f32) -> std::ops::Range<u32> {
+        clamp_range(from, to, self.image.height())
+    }
+
+    fn columns(&self, from: f32, to: f32) -> std::ops::Range<u32> {
+        clamp_range(from, to, self.image.width())
+    }
+}
+
+fn clamp_range(from: f32, to: f32, len: u32) -> std::ops::Range<u32> {
+    let clamp = |value: f32| value.round().max(0.0).min(len as f32) as u32;
+    clamp(from.min(to))..clamp(from.max(to))
+}
+
+fn to_rgba(color: [f32; 4]) -> Rgba<u8> {
+    Rgba(color.map(|channel| (channel.max(0.0).min(1.0) * 255.0).round() as u8))
+}
+
+/// draws `color` over `pixel` by its alpha.
+fn blend(pixel: &mut Rgba<u8>, color: [f32; 4]) {
+    let alpha = color[3];
+    if alpha <= 0.0 {
+        return;
+    }
+    let below = pixel.0.map(|channel| channel as f32 / 255.0);
+    *pixel = to_rgba([
+        color[0] * alpha + below[0] * (1.0 - alpha),
+        color[1] * alpha + below[1] * (1.0 - alpha),
+        color[2] * alpha + below[2] * (1.0 - alpha),
+        alpha + below[3] * (1.0 - alpha),
+    ]);
+}
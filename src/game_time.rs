@@ -0,0 +1,84 @@
+//! Time as the fight sees it.
+//!
+//! The simulation, the particles and the animations advance by
+//! `GameTime::delta_seconds` instead of the wall clock, so the fight can be
+//! frozen or slowed down while the menus, the camera and the music carry on.
+//! A landed punch freezes the fight for a few frames and a bullet for fewer,
+//! and the blow that takes a fighter's last life plays in slow motion. The
+//! life is only lost once the hit wears off, when `update_game_state` ends
+//! the match right away, so the slow motion starts with the blow.
+//! Online matches always run at full speed, the peer wouldn't wait.
+
+use bevy::prelude::*;
+
+use crate::sim::{Control, HitCause, SimEvent};
+use crate::Sim;
+
+// seconds of freeze after a hit
+const PUNCH_HIT_STOP: f32 = 0.1;
+const BULLET_HIT_STOP: f32 = 0.05;
+// wall clock seconds the finishing blow is slowed down for, and how slow
+const SLOW_MOTION: f32 = 1.5;
+const SLOW_MOTION_SCALE: f32 = 0.25;
+
+#[derive(Default)]
+pub struct GameTime {
+    /// seconds the fight stays frozen for
+    hit_stop: f32,
+    /// seconds the fight stays slowed down for
+    slow_motion: f32,
+    delta: f32,
+}
+
+impl GameTime {
+    /// the seconds of fight that passed this frame.
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta
+    }
+
+    /// how fast the fight runs right now, 1 is the wall clock.
+    pub fn scale(&self) -> f32 {
+        if self.hit_stop > 0.0 {
+            0.0
+        } else if self.slow_motion > 0.0 {
+            SLOW_MOTION_SCALE
+        } else {
+            1.0
+        }
+    }
+}
+
+/// freezes and slows down the fight for the hits of the last frame.
+pub fn update_game_time(
+    time: Res<Time>,
+    sim: Res<Sim>,
+    mut events: EventReader<SimEvent>,
+    mut game_time: ResMut<GameTime>,
+) {
+    for event in events.iter() {
+        if let SimEvent::Hit { fighter, cause, .. } = *event {
+            let hit_stop = match cause {
+                HitCause::Punch => PUNCH_HIT_STOP,
+                HitCause::Bullet => BULLET_HIT_STOP,
+                HitCause::Hazard => 0.0,
+            };
+            game_time.hit_stop = game_time.hit_stop.max(hit_stop);
+            // the life is lost once the hit wears off
+            let state = sim.simulation.fighter(fighter);
+            if state.lives == 1 && state.control != Control::Dummy {
+                game_time.slow_motion = SLOW_MOTION;
+            }
+        }
+    }
+    if sim.online {
+        *game_time = GameTime::default();
+    }
+
+    let dt = time.delta_seconds();
+    game_time.delta = dt * game_time.scale();
+    if game_time.hit_stop > 0.0 {
+        game_time.hit_stop = (game_time.hit_stop - dt).max(0.0);
+    } else {
+        game_time.slow_motion = (game_time.slow_motion - dt).max(0.0);
+    }
+}
@@ -0,0 +1,100 @@
+//! Gamepad support.
+//!
+//! Connected gamepads are kept in `GamepadLobby` in the order they were
+//! plugged in. Against the ai the first gamepad controls the player. In a
+//! versus match a single gamepad goes to player 2, since player 1 has the
+//! mouse to aim with; with two or more the first one goes to player 1.
+
+use bevy::prelude::*;
+
+use crate::sim::Fighter;
+
+const STICK_DEADZONE: f32 = 0.5;
+const AIM_DEADZONE: f32 = 0.2;
+// how far away a punch is aimed with the right stick fully tilted
+const AIM_REACH: f32 = 300.0;
+
+#[derive(Default)]
+pub struct GamepadLobby {
+    pub gamepads: Vec<Gamepad>,
+}
+
+impl GamepadLobby {
+    pub fn gamepad_for(&self, fighter: Fighter, versus: bool) -> Option<Gamepad> {
+        let index = match (fighter, versus, self.gamepads.len()) {
+            (Fighter::Player, false, _) => 0,
+            (Fighter::Player, true, 1) => return None,
+            (Fighter::Player, true, _) => 0,
+            (Fighter::Opponent, false, _) => return None,
+            (Fighter::Opponent, true, 1) => 0,
+            (Fighter::Opponent, true, _) => 1,
+        };
+        self.gamepads.get(index).copied()
+    }
+}
+
+pub fn update_gamepad_lobby(
+    mut lobby: ResMut<GamepadLobby>,
+    mut gamepad_event: EventReader<GamepadEvent>,
+) {
+    for event in gamepad_event.iter() {
+        match event {
+            GamepadEvent(gamepad, GamepadEventType::Connected) => {
+                if !lobby.gamepads.contains(gamepad) {
+                    lobby.gamepads.push(*gamepad);
+                }
+            }
+            GamepadEvent(gamepad, GamepadEventType::Disconnected) => {
+                lobby.gamepads.retain(|connected| connected != gamepad);
+            }
+            _ => (),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Sticks {
+    pub left: bool,
+    pub right: bool,
+    /// where the right stick aims the gloves, zero while it isn't tilted
+    pub aim: Vec2,
+}
+
+/// the left stick walks and the right stick aims, buttons are bound in the
+/// input map.
+pub fn sticks(gamepad: Gamepad, axes: &Axis<GamepadAxis>) -> Sticks {
+    let axis = |axis_type| axes.get(GamepadAxis(gamepad, axis_type)).unwrap_or(0.0);
+
+    let walk = axis(GamepadAxisType::LeftStickX);
+    let aim = Vec2::new(
+        axis(GamepadAxisType::RightStickX),
+        axis(GamepadAxisType::RightStickY),
+    );
+
+    Sticks {
+        left: walk < -STICK_DEADZONE,
+        right: walk > STICK_DEADZONE,
+        aim: if aim.length() > AIM_DEADZONE {
+            aim.clamp_length_max(1.0) * AIM_REACH
+        } else {
+            Vec2::ZERO
+        },
+    }
+}
+
+/// whether start was pressed on any connected gamepad.
+pub fn start_pressed(lobby: &GamepadLobby, buttons: &Input<GamepadButton>) -> bool {
+    just_pressed(lobby, buttons, GamepadButtonType::Start)
+}
+
+/// whether `button` was pressed on any connected gamepad.
+pub fn just_pressed(
+    lobby: &GamepadLobby,
+    buttons: &Input<GamepadButton>,
+    button: GamepadButtonType,
+) -> bool {
+    lobby
+        .gamepads
+        .iter()
+        .any(|gamepad| buttons.just_pressed(GamepadButton(*gamepad, button)))
+}
@@ -0,0 +1,230 @@
+//! Reinforcement learning interface over the headless simulation.
+//!
+//! The gym speaks a line based text protocol, either on stdin/stdout
+//! (`duel --gym`) or on a local tcp socket (`duel --gym-port 7777`).
+//! Every request is one line, every reply is one line:
+//!
+//! * `spec` -> `<observation length> <action count>`
+//! * `reset [seed]` -> `<observation>`
+//! * `step <action> [repeat]` -> `<observation> <reward> <done>`
+//! * `observe` -> `<observation>`
+//! * `quit` closes the session
+//!
+//! Observations are space separated floats, see `observe`. Actions are
+//! discrete and decoded by `decode_action`. Errors are answered with a line
+//! starting with `error`.
+
+use bevy::math::Vec2;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+
+use crate::sim::{Fighter, MatchSettings, PlayerInput, Simulation};
+use crate::stage::Stage;
+
+const MAX_BULLETS: usize = 16;
+pub const OBSERVATION_LEN: usize = 17 + MAX_BULLETS * 5;
+
+const MOVES: u32 = 3;
+const JUMPS: u32 = 2;
+// no punch plus eight directions
+const PUNCHES: u32 = 9;
+// the last action blocks
+const BLOCK_ACTION: u32 = MOVES * JUMPS * PUNCHES;
+pub const ACTION_COUNT: u32 = BLOCK_ACTION + 1;
+
+// how far away from the player a punch is aimed
+const PUNCH_REACH: f32 = 300.0;
+// episodes are cut off after five minutes of game time
+const MAX_EPISODE_TICKS: u64 = 60 * 60 * 5;
+
+/// a fight in the original arena, with the shipped weapons.
+fn new_simulation(seed: u64) -> Simulation {
+    Simulation::with_arena(seed, MatchSettings::default(), Stage::default().arena)
+}
+
+/// turns an action index into the input a human would give.
+///
+/// `action = movement + 3 * (jump + 2 * punch)` where movement is
+/// 0 none, 1 left, 2 right; jump is 0 or 1 and punch is 0 for no punch or
+/// 1..=8 for a punch towards `(punch - 1) * 45` degrees. `BLOCK_ACTION`
+/// blocks.
+pub fn decode_action(action: u32) -> Option<PlayerInput> {
+    if action >= ACTION_COUNT {
+        return None;
+    }
+    if action == BLOCK_ACTION {
+        return Some(PlayerInput {
+            block: true,
+            ..Default::default()
+        });
+    }
+    let movement = action % MOVES;
+    let jump = (action / MOVES) % JUMPS;
+    let punch = action / (MOVES * JUMPS);
+
+    let aim = if punch > 0 {
+        let angle = (punch - 1) as f32 * std::f32::consts::FRAC_PI_4;
+        Vec2::new(angle.cos(), angle.sin()) * PUNCH_REACH
+    } else {
+        Vec2::ZERO
+    };
+
+    Some(PlayerInput {
+        left: movement == 1,
+        right: movement == 2,
+        jump: jump == 1,
+        attack: punch > 0,
+        block: false,
+        aim,
+    })
+}
+
+/// flattens the simulation into a fixed size observation.
+///
+/// player x, y, vx, vy, lives, hit; opponent x, y, vx, vy, lives, hit; gloves
+/// offset x, y, punching; gun active; shotgun active; then for up to
+/// `MAX_BULLETS` bullets x, y, vx, vy, present. Velocities are in units
+/// per second.
+pub fn observe(sim: &Simulation) -> Vec<f32> {
+    let mut obs = Vec::with_capacity(OBSERVATION_LEN);
+    let flag = |value: bool| if value { 1.0 } else { 0.0 };
+    let player = sim.fighter(Fighter::Player);
+    let opponent = sim.fighter(Fighter::Opponent);
+
+    obs.extend([
+        player.pos.x,
+        player.pos.y,
+        player.vel.x,
+        player.vel.y,
+        player.lives as f32,
+        flag(player.is_hit()),
+        opponent.pos.x,
+        opponent.pos.y,
+        opponent.vel.x,
+        opponent.vel.y,
+        opponent.lives as f32,
+        flag(opponent.is_hit()),
+        player.gloves.offset.x,
+        player.gloves.offset.y,
+        flag(player.gloves.punch_ticks > 0),
+        flag(opponent.gun.is_active),
+        flag(opponent.shotgun.is_active),
+    ]);
+
+    for i in 0..MAX_BULLETS {
+        match sim.bullets.get(i) {
+            Some(bullet) => {
+                let vel = bullet.vel * crate::sim::TICK_RATE as f32;
+                obs.extend([bullet.pos.x, bullet.pos.y, vel.x, vel.y, 1.0]);
+            }
+            None => obs.extend([0.0; 5]),
+        }
+    }
+    obs
+}
+
+pub struct Gym {
+    sim: Simulation,
+}
+
+impl Gym {
+    pub fn new(seed: u64) -> Self {
+        Gym {
+            sim: new_simulation(seed),
+        }
+    }
+
+    pub fn reset(&mut self, seed: u64) -> Vec<f32> {
+        self.sim = new_simulation(seed);
+        observe(&self.sim)
+    }
+
+    /// steps `repeat` ticks with the same action. the reward is +1 for every
+    /// life the opponent loses and -1 for every life the player loses.
+    pub fn step(&mut self, input: &PlayerInput, repeat: u32) -> (Vec<f32>, f32, bool) {
+        let mut reward = 0.0;
+        for _ in 0..repeat.max(1) {
+            if self.is_done() {
+                break;
+            }
+            let player_lives = self.sim.fighter(Fighter::Player).lives;
+            let opponent_lives = self.sim.fighter(Fighter::Opponent).lives;
+            self.sim.step(&[*input, PlayerInput::default()]);
+            reward += (opponent_lives - self.sim.fighter(Fighter::Opponent).lives) as f32;
+            reward -= (player_lives - self.sim.fighter(Fighter::Player).lives) as f32;
+        }
+        (observe(&self.sim), reward, self.is_done())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.sim.winner().is_some() || self.sim.tick >= MAX_EPISODE_TICKS
+    }
+
+    /// answers one protocol line, `None` ends the session.
+    fn handle(&mut self, line: &str) -> Option<String> {
+        let mut words = line.split_whitespace();
+        let reply = match words.next() {
+            Some("spec") => format!("{} {}", OBSERVATION_LEN, ACTION_COUNT),
+            Some("reset") => match words.next().map(str::parse::<u64>) {
+                Some(Ok(seed)) => format_floats(&self.reset(seed)),
+                Some(Err(_)) => "error invalid seed".to_string(),
+                None => format_floats(&self.reset(0)),
+            },
+            Some("step") => {
+                let action = words.next().and_then(|word| word.parse::<u32>().ok());
+                let repeat = words.next().map(str::parse::<u32>).unwrap_or(Ok(1));
+                match (action.and_then(decode_action), repeat) {
+                    (Some(input), Ok(repeat)) => {
+                        let (obs, reward, done) = self.step(&input, repeat);
+                        format!("{} {} {}", format_floats(&obs), reward, done as u8)
+                    }
+                    _ => "error invalid action".to_string(),
+                }
+            }
+            Some("observe") => format_floats(&observe(&self.sim)),
+            Some("quit") => return None,
+            Some(command) => format!("error unknown command {}", command),
+            None => "error empty request".to_string(),
+        };
+        Some(reply)
+    }
+
+    pub fn serve<R: BufRead, W: Write>(&mut self, reader: R, mut writer: W) -> io::Result<()> {
+        for line in reader.lines() {
+            match self.handle(&line?) {
+                Some(reply) => {
+                    writeln!(writer, "{}", reply)?;
+                    writer.flush()?;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_floats(values: &[f32]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn run_stdio() -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    Gym::new(0).serve(stdin.lock(), stdout.lock())
+}
+
+/// serves one client at a time on localhost.
+pub fn run_tcp(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let reader = BufReader::new(stream.try_clone()?);
+        Gym::new(0).serve(reader, stream)?;
+    }
+    Ok(())
+}
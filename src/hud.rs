@@ -0,0 +1,447 @@
+//! The heads-up display of a match.
+//!
+//! Each fighter gets a panel in a top corner with a portrait, a health bar,
+//! a stock icon per life and, for weapon specialists, the gun and the
+//! shotgun with their reload. The match time sits between the panels and a
+//! combo counter shows up under a panel while its fighter keeps landing hits.
+//! Everything is sized in fractions of the window and the text grows with
+//! its height.
+
+use bevy::prelude::*;
+
+use crate::sim::{self, Control, Fighter, Role, SimEvent, Weapon};
+use crate::{fighter_image, Sim};
+
+// the window height the text sizes are picked for
+const BASE_HEIGHT: f32 = 720.0;
+// a hit within this many ticks of the last one continues the combo, a bit
+// longer than a hit keeps the other fighter from being hit again
+const COMBO_TICKS: u64 = 4 * sim::TICK_RATE as u64;
+
+const CLEAR: Color = Color::rgba(0.0, 0.0, 0.0, 0.0);
+const BAR_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+const LOST_STOCK: Color = Color::rgba(1.0, 1.0, 1.0, 0.25);
+const INACTIVE_WEAPON: Color = Color::rgba(1.0, 1.0, 1.0, 0.35);
+const RELOADING: Color = Color::rgb(0.6, 0.6, 0.6);
+
+/// hits in a row landed by each fighter.
+#[derive(Default)]
+pub struct Combos {
+    hits: [u32; 2],
+    /// the tick of each fighter's last hit
+    last_tick: [u64; 2],
+    /// the tick seen last, a new match starts over at a lower one
+    tick: u64,
+}
+
+/// text whose font size follows the window height, the size is for a
+/// window `BASE_HEIGHT` high.
+#[derive(Component)]
+pub struct ScaledText(pub f32);
+
+#[derive(Component)]
+pub struct Portrait {
+    fighter: Fighter,
+}
+
+#[derive(Component)]
+pub struct NameText {
+    fighter: Fighter,
+}
+
+#[derive(Component)]
+pub struct HealthBar {
+    fighter: Fighter,
+}
+
+#[derive(Component)]
+pub struct Stock {
+    fighter: Fighter,
+    life: u32,
+}
+
+/// the row with the weapons, hidden for boxers
+#[derive(Component)]
+pub struct WeaponRow {
+    fighter: Fighter,
+}
+
+#[derive(Component)]
+pub struct WeaponIcon {
+    fighter: Fighter,
+    weapon: Weapon,
+}
+
+#[derive(Component)]
+pub struct ReloadBar {
+    fighter: Fighter,
+    weapon: Weapon,
+}
+
+#[derive(Component)]
+pub struct ComboText {
+    fighter: Fighter,
+}
+
+#[derive(Component)]
+pub struct TimerText;
+
+fn weapon_image(weapon: Weapon) -> &'static str {
+    match weapon {
+        Weapon::Gun => "gun.png",
+        Weapon::Shotgun => "shotgun.png",
+    }
+}
+
+fn node(size: Size<Val>, color: Color) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            size,
+            ..Default::default()
+        },
+        color: UiColor(color),
+        ..Default::default()
+    }
+}
+
+fn text(font: &Handle<Font>) -> TextBundle {
+    TextBundle {
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: font.clone(),
+                font_size: 0.0,
+                color: Color::WHITE,
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    }
+}
+
+pub fn setup_hud(mut commands: Commands, server: Res<AssetServer>) {
+    let font = server.load("fonts/IceCaps.ttf");
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::SpaceBetween,
+                // bevy's ui has y going up, so this is the top
+                align_items: AlignItems::FlexEnd,
+                padding: Rect {
+                    left: Val::Percent(1.5),
+                    right: Val::Percent(1.5),
+                    top: Val::Percent(1.5),
+                    bottom: Val::Percent(1.5),
+                },
+                ..Default::default()
+            },
+            color: UiColor(CLEAR),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            spawn_panel(parent, &server, &font, Fighter::Player);
+            parent
+                .spawn_bundle(text(&font))
+                .insert(ScaledText(48.0))
+                .insert(TimerText);
+            spawn_panel(parent, &server, &font, Fighter::Opponent);
+        });
+}
+
+fn spawn_panel(
+    parent: &mut ChildBuilder,
+    server: &AssetServer,
+    font: &Handle<Font>,
+    fighter: Fighter,
+) {
+    // the opponent's panel mirrors the player's
+    let (row, align) = match fighter {
+        Fighter::Player => (FlexDirection::Row, AlignItems::FlexStart),
+        Fighter::Opponent => (FlexDirection::RowReverse, AlignItems::FlexEnd),
+    };
+    let gap = Rect {
+        left: Val::Percent(0.75),
+        right: Val::Percent(0.75),
+        ..Default::default()
+    };
+    let spacing = Rect {
+        top: Val::Percent(0.75),
+        bottom: Val::Percent(0.75),
+        ..Default::default()
+    };
+    let stocks = Role::Boxer.lives().max(Role::WeaponSpecialist.lives());
+
+    let mut panel = node(Size::new(Val::Percent(36.0), Val::Percent(20.0)), CLEAR);
+    panel.style.flex_direction = row;
+    parent.spawn_bundle(panel).with_children(|panel| {
+        panel
+            .spawn_bundle(ImageBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(12.0), Val::Percent(100.0)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Portrait { fighter });
+
+        let mut column = node(Size::new(Val::Percent(84.0), Val::Percent(100.0)), CLEAR);
+        column.style.margin = gap;
+        // from the top down
+        column.style.flex_direction = FlexDirection::ColumnReverse;
+        column.style.align_items = align;
+        panel.spawn_bundle(column).with_children(|column| {
+            column
+                .spawn_bundle(text(font))
+                .insert(ScaledText(30.0))
+                .insert(NameText { fighter });
+
+            let mut bar = node(
+                Size::new(Val::Percent(100.0), Val::Percent(16.0)),
+                BAR_BACKGROUND,
+            );
+            bar.style.flex_direction = row;
+            column.spawn_bundle(bar).with_children(|bar| {
+                bar.spawn_bundle(node(
+                    Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    Color::GREEN,
+                ))
+                .insert(HealthBar { fighter });
+            });
+
+            let mut stock_row = node(Size::new(Val::Percent(100.0), Val::Percent(9.0)), CLEAR);
+            stock_row.style.flex_direction = row;
+            stock_row.style.margin = spacing;
+            column.spawn_bundle(stock_row).with_children(|stock_row| {
+                for life in 0..stocks {
+                    let mut stock = node(
+                        Size::new(Val::Percent(4.0), Val::Percent(100.0)),
+                        Color::WHITE,
+                    );
+                    stock.style.margin = gap;
+                    stock_row
+                        .spawn_bundle(stock)
+                        .insert(Stock { fighter, life });
+                }
+            });
+
+            let mut weapon_row = node(Size::new(Val::Percent(100.0), Val::Percent(30.0)), CLEAR);
+            weapon_row.style.flex_direction = row;
+            weapon_row.style.margin = spacing;
+            column
+                .spawn_bundle(weapon_row)
+                .insert(WeaponRow { fighter })
+                .with_children(|weapon_row| {
+                    for weapon in Weapon::ALL {
+                        let mut slot =
+                            node(Size::new(Val::Percent(14.0), Val::Percent(100.0)), CLEAR);
+                        slot.style.margin = gap;
+                        slot.style.flex_direction = FlexDirection::ColumnReverse;
+                        weapon_row.spawn_bundle(slot).with_children(|slot| {
+                            slot.spawn_bundle(ImageBundle {
+                                style: Style {
+                                    size: Size::new(Val::Percent(100.0), Val::Percent(80.0)),
+                                    ..Default::default()
+                                },
+                                image: UiImage(server.load(weapon_image(weapon))),
+                                ..Default::default()
+                            })
+                            .insert(WeaponIcon { fighter, weapon });
+                            slot.spawn_bundle(node(
+                                Size::new(Val::Percent(100.0), Val::Percent(12.0)),
+                                BAR_BACKGROUND,
+                            ))
+                            .with_children(|bar| {
+                                bar.spawn_bundle(node(
+                                    Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                                    RELOADING,
+                                ))
+                                .insert(ReloadBar { fighter, weapon });
+                            });
+                        });
+                    }
+                });
+
+            column
+                .spawn_bundle(text(font))
+                .insert(ScaledText(26.0))
+                .insert(ComboText { fighter });
+        });
+    });
+}
+
+/// from red through yellow to green as `health` goes from 0 to 1.
+fn health_color(health: f32) -> Color {
+    Color::rgb(
+        (2.0 * (1.0 - health)).min(1.0),
+        (2.0 * health).min(1.0),
+        0.2,
+    )
+}
+
+pub fn update_combos(sim: Res<Sim>, mut events: EventReader<SimEvent>, mut combos: ResMut<Combos>) {
+    let tick = sim.simulation.tick;
+    if tick < combos.tick {
+        *combos = Combos::default();
+    }
+    combos.tick = tick;
+    for event in events.iter() {
+        if let SimEvent::Hit { fighter, .. } = *event {
+            let attacker = fighter.other().index();
+            combos.hits[attacker] += 1;
+            combos.last_tick[attacker] = tick;
+            combos.hits[fighter.index()] = 0;
+        }
+    }
+    for fighter in Fighter::ALL {
+        let index = fighter.index();
+        if tick - combos.last_tick[index] > COMBO_TICKS {
+            combos.hits[index] = 0;
+        }
+    }
+}
+
+pub fn update_health_bars(
+    sim: Res<Sim>,
+    mut bar_query: Query<(&HealthBar, &mut Style, &mut UiColor)>,
+) {
+    for (bar, mut style, mut color) in bar_query.iter_mut() {
+        let fighter = sim.simulation.fighter(bar.fighter);
+        // drains over the hit, until the life is gone
+        let lives = fighter.lives as f32 - fighter.hit_progress();
+        let health = (lives / fighter.role.lives() as f32).clamp(0.0, 1.0);
+        style.size.width = Val::Percent(health * 100.0);
+        color.0 = health_color(health);
+    }
+}
+
+pub fn update_stocks(sim: Res<Sim>, mut stock_query: Query<(&Stock, &mut Style, &mut UiColor)>) {
+    for (stock, mut style, mut color) in stock_query.iter_mut() {
+        let fighter = sim.simulation.fighter(stock.fighter);
+        let display = if stock.life < fighter.role.lives() {
+            Display::Flex
+        } else {
+            Display::None
+        };
+        if style.display != display {
+            style.display = display;
+        }
+        color.0 = if stock.life < fighter.lives {
+            Color::WHITE
+        } else {
+            LOST_STOCK
+        };
+    }
+}
+
+pub fn update_portraits(
+    sim: Res<Sim>,
+    server: Res<AssetServer>,
+    mut portrait_query: Query<(&Portrait, &mut UiImage, &mut UiColor)>,
+) {
+    for (portrait, mut image, mut color) in portrait_query.iter_mut() {
+        let fighter = sim.simulation.fighter(portrait.fighter);
+        let texture = server.load(fighter_image(fighter.role));
+        if image.0 != texture {
+            image.0 = texture;
+        }
+        color.0 = if fighter.lives == 0 {
+            Color::rgb(0.2, 0.2, 0.2)
+        } else if fighter.is_hit() {
+            Color::rgb(1.0, 0.0, 0.0)
+        } else {
+            Color::WHITE
+        };
+    }
+}
+
+pub fn update_weapon_indicators(
+    sim: Res<Sim>,
+    mut row_query: Query<(&WeaponRow, &mut Style), Without<ReloadBar>>,
+    mut icon_query: Query<(&WeaponIcon, &mut UiColor), Without<ReloadBar>>,
+    mut bar_query: Query<(&ReloadBar, &mut Style, &mut UiColor), Without<WeaponIcon>>,
+) {
+    for (row, mut style) in row_query.iter_mut() {
+        let display = match sim.simulation.fighter(row.fighter).role {
+            Role::WeaponSpecialist => Display::Flex,
+            Role::Boxer => Display::None,
+        };
+        if style.display != display {
+            style.display = display;
+        }
+    }
+    for (icon, mut color) in icon_query.iter_mut() {
+        let weapon = sim.simulation.fighter(icon.fighter).weapon(icon.weapon);
+        color.0 = if weapon.is_active {
+            Color::WHITE
+        } else {
+            INACTIVE_WEAPON
+        };
+    }
+    for (bar, mut style, mut color) in bar_query.iter_mut() {
+        let weapon = sim.simulation.fighter(bar.fighter).weapon(bar.weapon);
+        let reloaded =
+            1.0 - (weapon.reload_ticks as f32 / bar.weapon.reload_ticks() as f32).min(1.0);
+        style.size.width = Val::Percent(reloaded * 100.0);
+        color.0 = if weapon.reload_ticks == 0 {
+            Color::GOLD
+        } else {
+            RELOADING
+        };
+    }
+}
+
+pub fn update_hud_text(
+    sim: Res<Sim>,
+    combos: Res<Combos>,
+    mut text_query: Query<(
+        &mut Text,
+        Option<&NameText>,
+        Option<&ComboText>,
+        Option<&TimerText>,
+    )>,
+) {
+    for (mut text, name, combo, timer) in text_query.iter_mut() {
+        let value = if let Some(name) = name {
+            match (name.fighter, sim.simulation.settings.opponent_control) {
+                (Fighter::Player, _) => "Player".to_string(),
+                (Fighter::Opponent, Control::Human) => "Player 2".to_string(),
+                (Fighter::Opponent, Control::Ai) => "Enemy".to_string(),
+                (Fighter::Opponent, Control::Dummy) => "Dummy".to_string(),
+            }
+        } else if let Some(combo) = combo {
+            match combos.hits[combo.fighter.index()] {
+                hits if hits >= 2 => format!("{} hit combo", hits),
+                _ => String::new(),
+            }
+        } else if timer.is_some() {
+            let seconds = sim.simulation.tick / sim::TICK_RATE as u64;
+            format!("{}:{:02}", seconds / 60, seconds % 60)
+        } else {
+            continue;
+        };
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+pub fn scale_hud_text(windows: Res<Windows>, mut text_query: Query<(&ScaledText, &mut Text)>) {
+    let scale = match windows.get_primary() {
+        Some(window) => window.height() / BASE_HEIGHT,
+        None => return,
+    };
+    for (scaled, mut text) in text_query.iter_mut() {
+        let size = scaled.0 * scale;
+        if text
+            .sections
+            .iter()
+            .any(|section| section.style.font_size != size)
+        {
+            for section in text.sections.iter_mut() {
+                section.style.font_size = size;
+            }
+        }
+    }
+}
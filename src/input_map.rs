@@ -0,0 +1,498 @@
+//! Action layer between the input devices and the game.
+//!
+//! Gameplay reads `ActionState` instead of keys and buttons. Which keys and
+//! buttons trigger an action is described by `InputMap`, which is stored in
+//! the settings file and can be changed in the controls menu. Every line of
+//! it binds one action of one player, e.g.
+//!
+//! ```text
+//! player1.jump = W, Pad South
+//! player2.punch = RControl, Mouse Right
+//! ```
+
+use bevy::prelude::*;
+
+use std::fmt;
+
+use crate::gamepad::{self, GamepadLobby};
+use crate::sim::{Control, Fighter};
+use crate::Sim;
+
+// the length of `Action::ALL`
+const ACTIONS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Punch,
+    Block,
+    Pause,
+    /// while paused
+    QuitToTitle,
+    /// on the title screen
+    NextStage,
+}
+
+impl Action {
+    pub const ALL: [Action; ACTIONS] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Jump,
+        Action::Punch,
+        Action::Block,
+        Action::Pause,
+        Action::QuitToTitle,
+        Action::NextStage,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// the name used in the controls file.
+    fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::Jump => "jump",
+            Action::Punch => "punch",
+            Action::Block => "block",
+            Action::Pause => "pause",
+            Action::QuitToTitle => "quit_to_title",
+            Action::NextStage => "next_stage",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::Jump => "Jump",
+            Action::Punch => "Punch",
+            Action::Block => "Block",
+            Action::Pause => "Pause",
+            Action::QuitToTitle => "Quit to title",
+            Action::NextStage => "Next stage",
+        }
+    }
+}
+
+fn fighter_name(fighter: Fighter) -> &'static str {
+    match fighter {
+        Fighter::Player => "player1",
+        Fighter::Opponent => "player2",
+    }
+}
+
+// keys that can be written to and read from the controls file
+const KEYS: [KeyCode; 89] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Key0,
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::Escape,
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+    KeyCode::F10,
+    KeyCode::F11,
+    KeyCode::F12,
+    KeyCode::Insert,
+    KeyCode::Home,
+    KeyCode::Delete,
+    KeyCode::End,
+    KeyCode::PageDown,
+    KeyCode::PageUp,
+    KeyCode::Left,
+    KeyCode::Up,
+    KeyCode::Right,
+    KeyCode::Down,
+    KeyCode::Back,
+    KeyCode::Return,
+    KeyCode::Space,
+    KeyCode::Numpad0,
+    KeyCode::Numpad1,
+    KeyCode::Numpad2,
+    KeyCode::Numpad3,
+    KeyCode::Numpad4,
+    KeyCode::Numpad5,
+    KeyCode::Numpad6,
+    KeyCode::Numpad7,
+    KeyCode::Numpad8,
+    KeyCode::Numpad9,
+    KeyCode::Apostrophe,
+    KeyCode::Backslash,
+    KeyCode::Comma,
+    KeyCode::Equals,
+    KeyCode::Grave,
+    KeyCode::LAlt,
+    KeyCode::LBracket,
+    KeyCode::LControl,
+    KeyCode::LShift,
+    KeyCode::Minus,
+    KeyCode::Period,
+    KeyCode::RAlt,
+    KeyCode::RBracket,
+    KeyCode::RControl,
+    KeyCode::RShift,
+    KeyCode::Semicolon,
+    KeyCode::Slash,
+];
+
+const MOUSE_BUTTONS: [MouseButton; 3] =
+    [MouseButton::Left, MouseButton::Right, MouseButton::Middle];
+
+const GAMEPAD_BUTTONS: [GamepadButtonType; 19] = [
+    GamepadButtonType::South,
+    GamepadButtonType::East,
+    GamepadButtonType::North,
+    GamepadButtonType::West,
+    GamepadButtonType::C,
+    GamepadButtonType::Z,
+    GamepadButtonType::LeftTrigger,
+    GamepadButtonType::LeftTrigger2,
+    GamepadButtonType::RightTrigger,
+    GamepadButtonType::RightTrigger2,
+    GamepadButtonType::Select,
+    GamepadButtonType::Start,
+    GamepadButtonType::Mode,
+    GamepadButtonType::LeftThumb,
+    GamepadButtonType::RightThumb,
+    GamepadButtonType::DPadUp,
+    GamepadButtonType::DPadDown,
+    GamepadButtonType::DPadLeft,
+    GamepadButtonType::DPadRight,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// a button on the gamepad assigned to the player
+    Pad(GamepadButtonType),
+}
+
+impl Binding {
+    pub fn is_gamepad(&self) -> bool {
+        matches!(self, Binding::Pad(_))
+    }
+
+    fn parse(text: &str) -> Option<Binding> {
+        let named = |name: &str, debug: &dyn fmt::Debug| format!("{:?}", debug) == name;
+        if let Some(name) = text.strip_prefix("Mouse ") {
+            MOUSE_BUTTONS
+                .into_iter()
+                .find(|button| named(name, button))
+                .map(Binding::Mouse)
+        } else if let Some(name) = text.strip_prefix("Pad ") {
+            GAMEPAD_BUTTONS
+                .into_iter()
+                .find(|button| named(name, button))
+                .map(Binding::Pad)
+        } else {
+            KEYS.into_iter()
+                .find(|key| named(text, key))
+                .map(Binding::Key)
+        }
+    }
+
+    /// the binding for the first key or button pressed this frame, if any.
+    pub fn just_pressed(
+        keyboard_input: &Input<KeyCode>,
+        mouse_button: &Input<MouseButton>,
+        gamepad_buttons: &Input<GamepadButton>,
+    ) -> Option<Binding> {
+        let key = keyboard_input
+            .get_just_pressed()
+            .find(|key| KEYS.contains(key))
+            .map(|key| Binding::Key(*key));
+        let mouse = mouse_button
+            .get_just_pressed()
+            .find(|button| MOUSE_BUTTONS.contains(button))
+            .map(|button| Binding::Mouse(*button));
+        let pad = gamepad_buttons
+            .get_just_pressed()
+            .next()
+            .map(|button| Binding::Pad(button.1));
+        key.or(mouse).or(pad)
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{:?}", key),
+            Binding::Mouse(button) => write!(f, "Mouse {:?}", button),
+            Binding::Pad(button) => write!(f, "Pad {:?}", button),
+        }
+    }
+}
+
+/// the bindings of every action, indexed by `Fighter::index` and action.
+#[derive(Clone)]
+pub struct InputMap {
+    bindings: [[Vec<Binding>; ACTIONS]; 2],
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use Binding::{Key, Mouse, Pad};
+        use GamepadButtonType as Button;
+
+        let mut input_map = InputMap {
+            bindings: Default::default(),
+        };
+        let defaults = [
+            (
+                Action::MoveLeft,
+                [Key(KeyCode::A), Key(KeyCode::Left)],
+                Pad(Button::DPadLeft),
+            ),
+            (
+                Action::MoveRight,
+                [Key(KeyCode::D), Key(KeyCode::Right)],
+                Pad(Button::DPadRight),
+            ),
+            (
+                Action::Jump,
+                [Key(KeyCode::W), Key(KeyCode::Up)],
+                Pad(Button::South),
+            ),
+            (
+                Action::Punch,
+                [Mouse(MouseButton::Left), Key(KeyCode::RControl)],
+                Pad(Button::West),
+            ),
+            (
+                Action::Block,
+                [Key(KeyCode::S), Key(KeyCode::Down)],
+                Pad(Button::East),
+            ),
+            (
+                Action::Pause,
+                [Key(KeyCode::Escape), Key(KeyCode::P)],
+                Pad(Button::Start),
+            ),
+            (
+                Action::QuitToTitle,
+                [Key(KeyCode::Q), Key(KeyCode::End)],
+                Pad(Button::Select),
+            ),
+            (
+                Action::NextStage,
+                [Key(KeyCode::Tab), Key(KeyCode::PageDown)],
+                Pad(Button::North),
+            ),
+        ];
+        for (action, keys, pad) in defaults {
+            for fighter in Fighter::ALL {
+                let bindings = &mut input_map.bindings[fighter.index()][action.index()];
+                bindings.push(keys[fighter.index()]);
+                bindings.push(pad);
+            }
+        }
+        input_map.bindings[Fighter::Player.index()][Action::Punch.index()]
+            .push(Pad(Button::RightTrigger));
+        input_map.bindings[Fighter::Opponent.index()][Action::Punch.index()]
+            .push(Pad(Button::RightTrigger));
+        input_map
+    }
+}
+
+impl InputMap {
+    pub fn bindings(&self, fighter: Fighter, action: Action) -> &[Binding] {
+        &self.bindings[fighter.index()][action.index()]
+    }
+
+    /// the bindings of an action for hints on screen, e.g. `Q or Pad Select`.
+    pub fn names(&self, fighter: Fighter, action: Action) -> String {
+        self.bindings(fighter, action)
+            .iter()
+            .map(Binding::to_string)
+            .collect::<Vec<_>>()
+            .join(" or ")
+    }
+
+    /// binds an action, replacing its other bindings of the same kind of
+    /// device so a new key doesn't unbind the gamepad and vice versa.
+    pub fn bind(&mut self, fighter: Fighter, action: Action, binding: Binding) {
+        let bindings = &mut self.bindings[fighter.index()][action.index()];
+        bindings.retain(|bound| bound.is_gamepad() != binding.is_gamepad());
+        bindings.push(binding);
+    }
+
+    /// removes the keyboard and mouse bindings of an action.
+    pub fn clear(&mut self, fighter: Fighter, action: Action) {
+        self.bindings[fighter.index()][action.index()].retain(Binding::is_gamepad);
+    }
+
+    /// reads a `player1.jump = W, Pad South` line of the settings file,
+    /// returns false if it isn't a valid binding.
+    pub fn parse_line(&mut self, line: &str) -> bool {
+        let parsed = line.split_once('=').and_then(|(name, bindings)| {
+            let (fighter, action) = name.trim().split_once('.')?;
+            let fighter = Fighter::ALL
+                .into_iter()
+                .find(|candidate| fighter_name(*candidate) == fighter)?;
+            let action = Action::ALL
+                .into_iter()
+                .find(|candidate| candidate.name() == action)?;
+            let bindings = bindings
+                .split(',')
+                .map(str::trim)
+                .filter(|binding| !binding.is_empty())
+                .map(Binding::parse)
+                .collect::<Option<Vec<_>>>()?;
+            Some((fighter, action, bindings))
+        });
+
+        match parsed {
+            Some((fighter, action, bindings)) => {
+                self.bindings[fighter.index()][action.index()] = bindings;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// one line per action of every player, as read by `parse_line`.
+    pub fn to_config(&self) -> String {
+        let mut text = String::new();
+        for fighter in Fighter::ALL {
+            for action in Action::ALL {
+                let bindings = self
+                    .bindings(fighter, action)
+                    .iter()
+                    .map(Binding::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                text.push_str(&format!(
+                    "{}.{} = {}\n",
+                    fighter_name(fighter),
+                    action.name(),
+                    bindings
+                ));
+            }
+        }
+        text
+    }
+}
+
+/// which actions every player is triggering this frame.
+#[derive(Default)]
+pub struct ActionState {
+    pressed: [[bool; ACTIONS]; 2],
+    just_pressed: [[bool; ACTIONS]; 2],
+    aim: [Vec2; 2],
+}
+
+impl ActionState {
+    pub fn pressed(&self, fighter: Fighter, action: Action) -> bool {
+        self.pressed[fighter.index()][action.index()]
+    }
+
+    pub fn just_pressed(&self, fighter: Fighter, action: Action) -> bool {
+        self.just_pressed[fighter.index()][action.index()]
+    }
+
+    pub fn any_just_pressed(&self, action: Action) -> bool {
+        Fighter::ALL
+            .into_iter()
+            .any(|fighter| self.just_pressed(fighter, action))
+    }
+
+    /// aim from the gamepad's right stick, zero while it isn't tilted.
+    pub fn aim(&self, fighter: Fighter) -> Vec2 {
+        self.aim[fighter.index()]
+    }
+}
+
+pub fn update_action_state(
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_button: Res<Input<MouseButton>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    lobby: Res<GamepadLobby>,
+    input_map: Res<InputMap>,
+    sim: Res<Sim>,
+    mut state: ResMut<ActionState>,
+) {
+    let versus = sim.simulation.settings.opponent_control == Control::Human;
+    for fighter in Fighter::ALL {
+        let gamepad = lobby.gamepad_for(fighter, versus);
+        let sticks = gamepad
+            .map(|gamepad| gamepad::sticks(gamepad, &gamepad_axes))
+            .unwrap_or_default();
+
+        for action in Action::ALL {
+            let mut pressed = match action {
+                Action::MoveLeft => sticks.left,
+                Action::MoveRight => sticks.right,
+                _ => false,
+            };
+            let mut just_pressed = false;
+            for binding in input_map.bindings(fighter, action) {
+                match *binding {
+                    Binding::Key(key) => {
+                        pressed |= keyboard_input.pressed(key);
+                        just_pressed |= keyboard_input.just_pressed(key);
+                    }
+                    Binding::Mouse(button) => {
+                        pressed |= mouse_button.pressed(button);
+                        just_pressed |= mouse_button.just_pressed(button);
+                    }
+                    Binding::Pad(button_type) => {
+                        if let Some(gamepad) = gamepad {
+                            let button = GamepadButton(gamepad, button_type);
+                            pressed |= gamepad_buttons.pressed(button);
+                            just_pressed |= gamepad_buttons.just_pressed(button);
+                        }
+                    }
+                }
+            }
+            state.pressed[fighter.index()][action.index()] = pressed;
+            state.just_pressed[fighter.index()][action.index()] = just_pressed;
+        }
+        state.aim[fighter.index()] = sticks.aim;
+    }
+}
@@ -1,40 +1,271 @@
-use bevy::{
-    prelude::*,
-    sprite::collide_aabb::{collide, Collision},
-};
+use bevy::math::const_vec2;
+use bevy::prelude::*;
+
+use std::collections::HashMap;
+
+mod animation;
+mod audio;
+mod camera;
+mod controls_menu;
+mod editor;
+#[cfg(not(target_arch = "wasm32"))]
+mod export;
+mod game_time;
+mod gamepad;
+#[cfg(not(target_arch = "wasm32"))]
+mod gym;
+mod hud;
+mod input_map;
+mod menu;
+#[cfg(not(target_arch = "wasm32"))]
+mod netplay;
+mod particles;
+mod replay;
+mod settings;
+mod sim;
+mod stage;
+mod weapons;
+
+use animation::{Animation, Animations};
+use audio::GameAudio;
+use camera::CameraRig;
+use controls_menu::ControlsMenu;
+use editor::StageEditor;
+use game_time::GameTime;
+use gamepad::GamepadLobby;
+use hud::{Combos, ScaledText};
+use input_map::{Action, ActionState, InputMap};
+use menu::MainMenu;
+use replay::{Replay, ReplayViewer};
+use settings::Settings;
+use sim::{Control, Fighter, MatchSettings, PlayerInput, Role, SimEvent, Simulation};
+use stage::Stage;
 
-use nalgebra::{center, Isometry2, UnitComplex};
-
-use bevy_rapier2d::prelude::*;
-
-use std::time::Duration;
-
-use instant;
+fn main() {
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut netplay = None;
+    let mut viewer = ReplayViewer::default();
+    let mut editor = StageEditor::default();
+    let (settings, input_map, invalid_settings) = Settings::load();
+    let mut stage_id = settings.stage.clone();
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let args: Vec<String> = std::env::args().collect();
+        if args.iter().any(|arg| arg == "--gym") {
+            gym::run_stdio().expect("gym session failed");
+            return;
+        }
+        let gym_port = args
+            .iter()
+            .position(|arg| arg == "--gym-port")
+            .and_then(|i| args.get(i + 1))
+            .and_then(|port| port.parse::<u16>().ok());
+        if let Some(port) = gym_port {
+            gym::run_tcp(port).expect("gym server failed");
+            return;
+        }
+        if let Some(i) = args.iter().position(|arg| arg == "--export") {
+            match (args.get(i + 1), args.get(i + 2)) {
+                (Some(replay), Some(output)) => {
+                    let options = export::ExportOptions::from_args(&args);
+                    export::run(replay.as_ref(), output.as_ref(), &options)
+                        .expect("replay export failed");
+                }
+                _ => eprintln!("usage: duel --export <replay> <output directory or .gif>"),
+            }
+            return;
+        }
 
-use rand::Rng;
+        let arg = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+        };
+        let conditions = netplay::NetConditions {
+            latency: std::time::Duration::from_millis(
+                arg("--net-latency")
+                    .and_then(|millis| millis.parse().ok())
+                    .unwrap_or(0),
+            ),
+            loss: arg("--net-loss")
+                .and_then(|percent| percent.parse::<f32>().ok())
+                .map_or(0.0, |percent| percent / 100.0),
+        };
+        if let Some(id) = arg("--stage") {
+            stage_id = id.clone();
+        }
+        if let Some(port) = arg("--host").and_then(|port| port.parse::<u16>().ok()) {
+            netplay = Some(
+                netplay::NetSession::host(port, conditions, Stage::load_or_default(&stage_id))
+                    .expect("could not host"),
+            );
+        } else if let Some(addr) = arg("--join") {
+            netplay = Some(netplay::NetSession::join(addr, conditions).expect("could not join"));
+        }
+        if let Some(path) = arg("--replay") {
+            viewer.open(Replay::load(path.as_ref()).expect("could not load the replay"));
+        }
+        if let Some(id) = arg("--edit") {
+            // a stage that doesn't exist yet starts out as the original arena
+            editor.open(Stage::load(id).unwrap_or_else(|_| Stage {
+                id: id.clone(),
+                name: id.clone(),
+                ..Default::default()
+            }));
+        }
+    }
 
-fn main() {
-    App::new()
+    let stage = Stage::load_or_default(&stage_id);
+    let mut app = App::new();
+    app.insert_resource(settings::window_descriptor(&settings))
         .add_plugins(DefaultPlugins)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_startup_system(setup_world.system())
-        .add_system(update_player_movement)
-        .add_system(update_ai_movement)
-        .add_system(update_gloves_position)
-        .add_system(update_gun_position)
-        .add_system(update_shotgun_position)
-        .add_system(update_bullets)
-        .add_system(player_lifes_update_system)
-        .add_system(enemy_lifes_update_system)
-        .add_system(update_game_state)
-        .run();
+        .add_plugin(bevy_kira_audio::AudioPlugin)
+        .insert_resource(Sim {
+            simulation: Simulation::with_arena(
+                rand::random(),
+                MatchSettings::default(),
+                stage.arena.clone(),
+            ),
+            stage,
+            accumulator: 0.0,
+            online: false,
+            inputs: Vec::new(),
+        })
+        .init_resource::<GamepadLobby>()
+        .insert_resource(input_map)
+        .init_resource::<ActionState>()
+        .init_resource::<ControlsMenu>()
+        .insert_resource(viewer)
+        .insert_resource(editor)
+        .init_resource::<CameraRig>()
+        .init_resource::<Combos>()
+        .insert_resource(settings)
+        .insert_resource(invalid_settings)
+        .init_resource::<GameAudio>()
+        .init_resource::<Animations>()
+        .init_resource::<GameTime>()
+        .init_resource::<BulletPool>()
+        .init_resource::<MainMenu>()
+        .add_event::<SimEvent>()
+        .add_startup_system(settings::warn_invalid_lines)
+        .add_startup_system(setup_world)
+        .add_startup_system(hud::setup_hud)
+        .add_startup_system(menu::setup_main_menu)
+        .add_startup_system(controls_menu::setup_controls_menu)
+        .add_startup_system(replay::setup_replay_viewer)
+        .add_startup_system(editor::setup_stage_editor)
+        .add_system(gamepad::update_gamepad_lobby.before(GameSystem::Input))
+        .add_system(game_time::update_game_time.before(GameSystem::Simulation))
+        .add_system(input_map::update_action_state.label(GameSystem::Input))
+        .add_system(
+            controls_menu::update_controls_menu
+                .label(GameSystem::Menu)
+                .after(GameSystem::Input),
+        )
+        .add_system(
+            menu::update_main_menu
+                .label(GameSystem::Menu)
+                .after(GameSystem::Input)
+                .after(controls_menu::update_controls_menu),
+        )
+        .add_system(menu::update_menu_text.after(menu::update_main_menu))
+        .add_system(settings::apply_window_mode.after(menu::update_main_menu))
+        .add_system(settings::remember_window_size)
+        .add_system(
+            settings::save_settings
+                .after(GameSystem::Menu)
+                .after(settings::remember_window_size),
+        )
+        .add_system(
+            replay::update_replay_viewer
+                .label(GameSystem::Simulation)
+                .after(GameSystem::Input),
+        )
+        .add_system(
+            editor::update_stage_editor
+                .label(GameSystem::Simulation)
+                .after(GameSystem::Input),
+        )
+        .add_system(camera::update_camera.after(GameSystem::Simulation))
+        .add_system(stage::update_stage_sprites.after(camera::update_camera))
+        .add_system(stage::update_platform_sprites.after(GameSystem::Simulation))
+        .add_system(editor::update_editor_overlay.after(GameSystem::Simulation))
+        .add_system(update_body_sprites.after(GameSystem::Simulation))
+        .add_system(animation::animate_fighters.after(GameSystem::Simulation))
+        .add_system(update_gloves_sprites.after(GameSystem::Simulation))
+        .add_system(update_gun_sprites.after(GameSystem::Simulation))
+        .add_system(update_shotgun_sprites.after(GameSystem::Simulation))
+        .add_system(update_bullets.after(GameSystem::Simulation))
+        .add_system(hud::update_combos.after(GameSystem::Simulation))
+        .add_system(hud::update_health_bars.after(GameSystem::Simulation))
+        .add_system(hud::update_stocks.after(GameSystem::Simulation))
+        .add_system(hud::update_portraits.after(GameSystem::Simulation))
+        .add_system(hud::update_weapon_indicators.after(GameSystem::Simulation))
+        .add_system(hud::update_hud_text.after(hud::update_combos))
+        .add_system(hud::scale_hud_text)
+        .add_system(audio::play_event_sounds.after(camera::update_camera))
+        .add_system(particles::spawn_particles.after(GameSystem::Simulation))
+        .add_system(particles::update_particles.after(particles::spawn_particles))
+        .add_system(
+            audio::update_music
+                .after(GameSystem::Simulation)
+                .after(menu::update_main_menu),
+        )
+        .add_system(
+            update_game_state
+                .after(GameSystem::Simulation)
+                .after(GameSystem::Menu),
+        );
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(session) = netplay {
+        app.insert_resource(session).add_system(
+            update_netplay
+                .label(GameSystem::Simulation)
+                .after(GameSystem::Input),
+        );
+        app.run();
+        return;
+    }
+
+    app.add_system(
+        update_simulation
+            .label(GameSystem::Simulation)
+            .after(GameSystem::Input)
+            .after(GameSystem::Menu),
+    );
+    app.run();
 }
 
-#[derive(Component)]
-struct PlayerText;
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+enum GameSystem {
+    Input,
+    Menu,
+    Simulation,
+}
 
-#[derive(Component)]
-struct AiText;
+// most frames the simulation only has to catch up one or two ticks, this
+// stops it from spiralling after a long stall
+const MAX_TICKS_PER_FRAME: u32 = 5;
+
+// the sprites in the world, the editor and the replay export draw them the
+// same size
+const BODY_SIZE: Vec2 = const_vec2!([40.96, 163.84]);
+const GLOVES_SIZE: Vec2 = const_vec2!([32.0, 32.0]);
+const WEAPON_SIZE: Vec2 = const_vec2!([64.0, 64.0]);
+const BULLET_SIZE: Vec2 = const_vec2!([16.0, 16.0]);
+
+/// the fight itself, everything on screen mirrors this.
+struct Sim {
+    simulation: Simulation,
+    accumulator: f32,
+    /// a netplay match, the menus stay out of the way
+    online: bool,
+    /// every tick of the current match, saved as a replay at the end
+    inputs: Vec<[PlayerInput; 2]>,
+    /// where the fight takes place, `simulation.arena` is built from it
+    stage: Stage,
+}
 
 #[derive(Component)]
 struct WinLoseText;
@@ -45,434 +276,121 @@ struct GameState {
 }
 
 #[derive(Component)]
-struct Player {
-    is_jumping: bool,
-    jump_duration: Duration,
-    end_jump: bool,
-    lives: u32,
-    hit: bool,
-    hit_duration: Duration,
-    elapsed: instant::Instant,
-    elapsed_hit: instant::Instant,
-    can_jump: bool,
-}
-
-#[derive(Component)]
-struct Ai {
-    hit: bool,
-    hit_duration: Duration,
-    elapsed_hit: instant::Instant,
-    lives: u32,
-    walk_x: i32,
-    jump_y: i32,
-    jump: bool,
-    elapsed_jump: instant::Instant,
-    jump_duration: Duration,
-    jump_end: bool,
-}
-
-#[derive(Component)]
-enum Collider {
-    Solid,
-    Bullet,
-    Gloves,
+struct Body {
+    fighter: Fighter,
 }
 
 #[derive(Component)]
 struct Gloves {
-    is_shooting: bool,
-    shot_duration: Duration,
-    end_shot: bool,
-    point_of_rotation: Point<f32>,
-    offset: Vec2,
-    elapsed: instant::Instant,
+    fighter: Fighter,
 }
 
 #[derive(Component)]
 struct Gun {
-    point_of_rotation: Point<f32>,
-    is_active: bool,
-    do_shot: bool,
-    shot_duration: Duration,
-    elapsed: instant::Instant,
+    fighter: Fighter,
 }
 
 #[derive(Component)]
 struct Shotgun {
-    do_shot: bool,
-    shot_duration: Duration,
-    elapsed: instant::Instant,
-    is_active: bool,
+    fighter: Fighter,
 }
 
+/// the sprite of a bullet of the simulation.
 #[derive(Component)]
 struct Bullet {
-    lifetime: Duration,
-    shoot_dir: Vec2,
-    elapsed: instant::Instant,
+    /// of the bullet shown, none while the sprite waits in the pool
+    id: Option<u32>,
+}
+
+/// hidden bullet sprites kept around to show the next bullets, so shooting
+/// doesn't spawn and despawn entities all the time.
+struct BulletPool {
+    texture: Handle<Image>,
+    free: Vec<Entity>,
 }
 
-fn setup_world(mut commands: Commands, asset_server: Res<AssetServer>) {
+impl FromWorld for BulletPool {
+    fn from_world(world: &mut World) -> Self {
+        let server = world.get_resource::<AssetServer>().unwrap();
+        BulletPool {
+            texture: server.load("bullet.png"),
+            free: Vec::new(),
+        }
+    }
+}
+
+fn setup_world(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    animations: Res<Animations>,
+    sim: Res<Sim>,
+) {
     // images
     let boxing_gloves_image: Handle<Image> = asset_server.load("boxing_gloves.png");
-    let boxer_image: Handle<Image> = asset_server.load("boxer.png");
     let gun_image: Handle<Image> = asset_server.load("gun.png");
     let shotgun_image: Handle<Image> = asset_server.load("shotgun.png");
-    let weapon_specialist_image: Handle<Image> = asset_server.load("weapon_specialist.png");
 
     // cameras
     commands.spawn_bundle(OrthographicCameraBundle::new_2d());
     commands.spawn_bundle(UiCameraBundle::default());
 
-    let ground_shape = SpriteBundle {
-        transform: Transform {
-            scale: Vec3::new(800.0, 50.0, 0.0),
-            ..Default::default()
-        },
-        sprite: Sprite {
-            color: Color::rgb(0.0, 0.0, 0.0),
-            ..Default::default()
-        },
-        ..Default::default()
-    };
-
-    let ground_rigid_body = RigidBodyBundle {
-        body_type: RigidBodyTypeComponent(RigidBodyType::Static),
-        position: Vec2::new(0.0, -200.0).into(),
-        activation: RigidBodyActivation::cannot_sleep().into(),
-        ccd: RigidBodyCcd {
-            ccd_enabled: true,
-            ..Default::default()
-        }
-        .into(),
-        ..Default::default()
-    };
-    /* creating gloves */
-    let boxing_gloves = SpriteBundle {
-        texture: boxing_gloves_image,
-        transform: Transform {
-            translation: Vec3::new(0.0, 0.0, 1.0),
-            scale: Vec3::new(64.0, 64.0, 0.0),
-            ..Default::default()
-        },
-        sprite: Sprite {
-            color: Color::rgb(1.0, 1.0, 1.0),
-            custom_size: Some(Vec2::new(0.5, 0.5)),
-            ..Default::default()
-        },
-        ..Default::default()
-    };
-
-    /* boxing_gloves rigid body. */
-    let boxing_gloves_rigid_body = RigidBodyBundle {
-        body_type: RigidBodyTypeComponent(RigidBodyType::KinematicVelocityBased),
-        position: Vec2::new(0.0, 200.0).into(),
-        velocity: RigidBodyVelocity {
-            linvel: Vec2::new(0.0, 0.0).into(),
-            angvel: 0.0,
-        }
-        .into(),
-        activation: RigidBodyActivation::cannot_sleep().into(),
-        ccd: RigidBodyCcd {
-            ccd_enabled: true,
-            ..Default::default()
-        }
-        .into(),
-        ..Default::default()
-    };
-
-    /* create player */
-    let player = SpriteBundle {
-        texture: boxer_image,
-        transform: Transform {
-            translation: Vec3::new(0.0, 0.0, 0.0),
-            scale: Vec3::new(64.0, 128.0, 0.0),
-            ..Default::default()
-        },
-        sprite: Sprite {
-            color: Color::rgb(1.0, 1.0, 1.0),
-            custom_size: Some(Vec2::new(0.64, 1.28)),
-            ..Default::default()
-        },
-        ..Default::default()
-    };
-
-    /* Create the bouncing ball. */
-    let player_rigid_body = RigidBodyBundle {
-        body_type: RigidBodyTypeComponent(RigidBodyType::KinematicVelocityBased),
-        position: Vec2::new(0.0, 200.0).into(),
-        velocity: RigidBodyVelocity {
-            linvel: Vec2::new(0.0, 0.0).into(),
-            angvel: 0.0,
-        }
-        .into(),
-        activation: RigidBodyActivation::cannot_sleep().into(),
-        ccd: RigidBodyCcd {
-            ccd_enabled: true,
-            ..Default::default()
-        }
-        .into(),
-        ..Default::default()
-    };
-
-    /* create AI */
-    let ai = SpriteBundle {
-        texture: weapon_specialist_image,
-        transform: Transform {
-            scale: Vec3::new(64.0, 128.0, 0.0),
-            ..Default::default()
-        },
-        sprite: Sprite {
-            color: Color::rgb(1.0, 1.0, 1.0),
-            custom_size: Some(Vec2::new(0.64, 1.28)),
-            ..Default::default()
-        },
-        ..Default::default()
-    };
-
-    /* Create the bouncing ball. */
-    let ai_rigid_body = RigidBodyBundle {
-        body_type: RigidBodyTypeComponent(RigidBodyType::KinematicVelocityBased),
-        position: Vec2::new(300.0, 200.0).into(),
-        velocity: RigidBodyVelocity {
-            linvel: Vec2::new(0.0, 5.0).into(),
-            angvel: 0.0,
-        }
-        .into(),
-        activation: RigidBodyActivation::cannot_sleep().into(),
-        ccd: RigidBodyCcd {
-            ccd_enabled: true,
-            ..Default::default()
-        }
-        .into(),
-        ..Default::default()
-    };
-
-    let gun = SpriteBundle {
-        texture: gun_image,
-        transform: Transform {
-            translation: Vec3::new(0.0, 0.0, 1.0),
-            scale: Vec3::new(64.0, 64.0, 0.0),
-            ..Default::default()
-        },
-        sprite: Sprite {
-            color: Color::rgba(1.0, 1.0, 1.0, 0.0),
-            custom_size: Some(Vec2::new(1.0, 1.0)),
-            ..Default::default()
-        },
-        ..Default::default()
-    };
-    /* boxing_gloves rigid body. */
-    let gun_rigid_body = RigidBodyBundle {
-        body_type: RigidBodyTypeComponent(RigidBodyType::KinematicVelocityBased),
-        velocity: RigidBodyVelocity {
-            linvel: Vec2::new(0.0, 0.0).into(),
-            angvel: 0.0,
-        }
-        .into(),
-        activation: RigidBodyActivation::cannot_sleep().into(),
-        ccd: RigidBodyCcd {
-            ccd_enabled: true,
-            ..Default::default()
-        }
-        .into(),
-        ..Default::default()
-    };
-
-    let shotgun = SpriteBundle {
-        texture: shotgun_image,
-        transform: Transform {
-            translation: Vec3::new(0.0, 0.0, 1.0),
-            scale: Vec3::new(64.0, 64.0, 0.0),
-            ..Default::default()
-        },
-        sprite: Sprite {
-            color: Color::rgba(1.0, 1.0, 1.0, 1.0),
-            custom_size: Some(Vec2::new(1.0, 1.0)),
-            ..Default::default()
-        },
-        ..Default::default()
-    };
-    /* boxing_gloves rigid body. */
-    let shotgun_rigid_body = RigidBodyBundle {
-        body_type: RigidBodyTypeComponent(RigidBodyType::KinematicVelocityBased),
-        position: Vec2::new(300.0, 200.0).into(),
-        velocity: RigidBodyVelocity {
-            linvel: Vec2::new(0.0, 0.0).into(),
-            angvel: 0.0,
-        }
-        .into(),
-        activation: RigidBodyActivation::cannot_sleep().into(),
-        ccd: RigidBodyCcd {
-            ccd_enabled: true,
-            ..Default::default()
-        }
-        .into(),
-        ..Default::default()
-    };
-    let background = SpriteBundle {
-        transform: Transform {
-            translation: Vec3::new(0.0, 0.0, 0.0),
-            scale: Vec3::new(9000.0, 9000.0, 0.0),
-            ..Default::default()
-        },
-        sprite: Sprite {
-            color: Color::rgba(0.5, 0.5, 1.0, 1.0),
-            ..Default::default()
-        },
-        ..Default::default()
-    };
-
-    commands.spawn_bundle(background);
-    commands
-        .spawn_bundle(player)
-        .insert_bundle(player_rigid_body)
-        .insert(Collider::Solid)
-        .insert(RigidBodyPositionSync::Discrete)
-        .insert(Player {
-            is_jumping: false,
-            jump_duration: Duration::new(0, 0),
-            end_jump: true,
-            lives: 5,
-            hit_duration: Duration::new(0, 0),
-            hit: false,
-            elapsed: instant::Instant::now(),
-            elapsed_hit: instant::Instant::now(),
-            can_jump: false,
-        });
-
-    commands
-        .spawn_bundle(ai)
-        .insert_bundle(ai_rigid_body)
-        .insert(RigidBodyPositionSync::Discrete)
-        .insert(Ai {
-            hit: false,
-            hit_duration: Duration::new(0, 0),
-            lives: 10,
-            elapsed_hit: instant::Instant::now(),
-            jump_y: 0,
-            walk_x: 0,
-            jump: false,
-            elapsed_jump: instant::Instant::now(),
-            jump_duration: Duration::from_millis(300),
-            jump_end: true,
-        });
-    commands
-        .spawn_bundle(ground_shape)
-        .insert_bundle(ground_rigid_body)
-        .insert(Collider::Solid)
-        .insert(RigidBodyPositionSync::Discrete);
-    commands
-        .spawn_bundle(boxing_gloves)
-        .insert(Gloves {
-            is_shooting: false,
-            end_shot: true,
-            shot_duration: Duration::new(0, 0),
-            point_of_rotation: Point::new(0.0, 0.0),
-            offset: Vec2::new(0.0, 0.0),
-            elapsed: instant::Instant::now(),
-        })
-        .insert_bundle(boxing_gloves_rigid_body)
-        .insert(Collider::Gloves)
-        .insert(RigidBodyPositionSync::Discrete);
-
-    commands
-        .spawn_bundle(gun)
-        .insert_bundle(gun_rigid_body)
-        .insert(Gun {
-            point_of_rotation: Point::new(0.0, 0.0),
-            is_active: true,
-            elapsed: instant::Instant::now(),
-            do_shot: false,
-            shot_duration: Duration::from_millis(256),
-        })
-        .insert(RigidBodyPositionSync::Discrete);
-
-    commands
-        .spawn_bundle(shotgun)
-        .insert_bundle(shotgun_rigid_body)
-        .insert(Shotgun {
-            do_shot: false,
-            shot_duration: Duration::new(5, 0),
-            elapsed: instant::Instant::now(),
-            is_active: false,
-        })
-        .insert(RigidBodyPositionSync::Discrete);
-
-    // Text
-    commands
-        .spawn_bundle(TextBundle {
-            style: Style {
-                align_self: AlignSelf::FlexEnd,
+    for fighter in Fighter::ALL {
+        /* create fighter */
+        let body = SpriteSheetBundle {
+            texture_atlas: animations.atlas(sim.simulation.fighter(fighter).role),
+            transform: Transform::from_xyz(0.0, 0.0, 0.0),
+            sprite: TextureAtlasSprite {
+                color: Color::rgb(1.0, 1.0, 1.0),
+                custom_size: Some(BODY_SIZE),
                 ..Default::default()
             },
-            // Use `Text` directly
-            text: Text {
-                // Construct a `Vec` of `TextSection`s
-                sections: vec![
-                    TextSection {
-                        value: "Player Lifes".to_string(),
-                        style: TextStyle {
-                            font_size: 60.0,
-                            font: asset_server.load("fonts/IceCaps.ttf"),
-                            color: Color::WHITE,
-                        },
-                    },
-                    TextSection {
-                        value: "".to_string(),
-                        style: TextStyle {
-                            font_size: 60.0,
-                            font: asset_server.load("fonts/IceCaps.ttf"),
-                            color: Color::GOLD,
-                            ..Default::default()
-                        },
-                    },
-                ],
+            ..Default::default()
+        };
+
+        /* creating gloves */
+        let boxing_gloves = SpriteBundle {
+            texture: boxing_gloves_image.clone(),
+            transform: Transform::from_xyz(0.0, 0.0, 1.0),
+            sprite: Sprite {
+                color: Color::rgb(1.0, 1.0, 1.0),
+                custom_size: Some(GLOVES_SIZE),
                 ..Default::default()
             },
             ..Default::default()
-        })
-        .insert(PlayerText);
-
-    // Text
-    commands
-        .spawn_bundle(TextBundle {
-            style: Style {
-                align_self: AlignSelf::FlexEnd,
-                position_type: PositionType::Absolute,
-                position: Rect {
-                    right: Val::Px(20.0),
-                    ..Default::default()
-                },
+        };
+
+        let gun = SpriteBundle {
+            texture: gun_image.clone(),
+            transform: Transform::from_xyz(0.0, 0.0, 1.0),
+            sprite: Sprite {
+                color: Color::rgba(1.0, 1.0, 1.0, 0.0),
+                custom_size: Some(WEAPON_SIZE),
                 ..Default::default()
             },
-            // Use `Text` directly
-            text: Text {
-                // Construct a `Vec` of `TextSection`s
-                sections: vec![
-                    TextSection {
-                        value: "Enemy Lifes".to_string(),
-                        style: TextStyle {
-                            font_size: 60.0,
-                            font: asset_server.load("fonts/IceCaps.ttf"),
-                            color: Color::WHITE,
-                        },
-                    },
-                    TextSection {
-                        value: "".to_string(),
-                        style: TextStyle {
-                            font_size: 60.0,
-                            font: asset_server.load("fonts/IceCaps.ttf"),
-                            color: Color::GOLD,
-                            ..Default::default()
-                        },
-                    },
-                ],
+            ..Default::default()
+        };
+
+        let shotgun = SpriteBundle {
+            texture: shotgun_image.clone(),
+            transform: Transform::from_xyz(0.0, 0.0, 1.0),
+            sprite: Sprite {
+                color: Color::rgba(1.0, 1.0, 1.0, 0.0),
+                custom_size: Some(WEAPON_SIZE),
                 ..Default::default()
             },
             ..Default::default()
-        })
-        .insert(AiText);
+        };
+
+        commands
+            .spawn_bundle(body)
+            .insert(Body { fighter })
+            .insert(Animation::default());
+        commands
+            .spawn_bundle(boxing_gloves)
+            .insert(Gloves { fighter });
+        commands.spawn_bundle(gun).insert(Gun { fighter });
+        commands.spawn_bundle(shotgun).insert(Shotgun { fighter });
+    }
 
     // Text
     commands
@@ -514,770 +432,394 @@ fn setup_world(mut commands: Commands, asset_server: Res<AssetServer>) {
             },
             ..Default::default()
         })
+        .insert(ScaledText(60.0))
         .insert(WinLoseText);
 
-    commands
-        .spawn_bundle(RigidBodyBundle {
-            ..Default::default()
-        })
-        .insert(GameState { state: 0 });
+    commands.spawn().insert(GameState { state: 0 });
 }
 
-fn update_player_movement(
-    keyboard_input: Res<Input<KeyCode>>,
-    mut game_state_query: Query<&GameState>,
-    mut player_query: Query<
-        (
-            &mut Player,
-            &mut RigidBodyPositionComponent,
-            &mut RigidBodyVelocityComponent,
-            &mut Sprite,
-        ),
-        With<Player>,
-    >,
-    collider_query: Query<(Entity, &Collider, &RigidBodyPositionComponent), Without<Player>>,
-    server: Res<AssetServer>,
-    audio: Res<Audio>,
+fn update_simulation(
+    game_time: Res<GameTime>,
+    actions: Res<ActionState>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &Transform)>,
+    game_state_query: Query<&GameState>,
+    mut sim: ResMut<Sim>,
+    mut sim_events: EventWriter<SimEvent>,
+    mut was_running: Local<bool>,
 ) {
-    for (mut player, mut position, mut velocities, mut sprite) in player_query.iter_mut() {
-        for game_state in game_state_query.iter_mut() {
-            let mut velocity = Vec2::new(velocities.0.linvel.x, velocities.0.linvel.y - 500.0);
-            for (entity, collider, transform) in collider_query.iter() {
-                if game_state.state == 1 {
-                    if player.is_jumping {
-                        player.can_jump = false;
-                    }
-                    if keyboard_input.pressed(KeyCode::A) {
-                        velocity.x = -200.0;
-                    }
-                    if keyboard_input.pressed(KeyCode::D) {
-                        velocity.x = 200.0;
-                    }
-
-                    if keyboard_input.just_pressed(KeyCode::W) {
-                        if player.can_jump {
-                            player.jump_duration = Duration::new(1, 0);
-                            player.elapsed = instant::Instant::now();
-
-                            player.is_jumping = true;
-                        }
-                    }
-
-                    let collision = collide(
-                        Vec3::new(
-                            position.0.position.translation.x,
-                            position.0.position.translation.y - 15.0, // y_offset
-                            0.0,
-                        ),
-                        Vec2::new(64.0, 128.0),
-                        Vec3::new(
-                            transform.0.position.translation.x,
-                            transform.0.position.translation.y,
-                            0.0,
-                        ),
-                        if matches!(collider, Collider::Solid) {
-                            Vec2::new(700.0, 50.0)
-                        } else {
-                            Vec2::new(32.0, 32.0)
-                        },
-                    );
-
-                    if let Some(collision) = collision {
-                        if let Collider::Solid = *collider {
-                            match collision {
-                                Collision::Top => {
-                                    velocity.y = 0.0;
-                                    player.is_jumping = false;
-                                    player.end_jump = false;
-                                    player.can_jump = true;
-                                }
-                                _ => {}
-                            }
-                        }
-                        if let Collider::Bullet = *collider {
-                            match collision {
-                                _ => {
-                                    if !player.hit {
-                                        let music: Handle<AudioSource> =
-                                            server.load("audio/hit-someting-6037.ogg");
-                                        audio.play(music);
-                                        player.hit_duration = Duration::new(3, 0);
-                                        player.elapsed_hit = instant::Instant::now();
-                                    }
-                                    player.hit = true;
-                                    sprite.color = Color::rgb(1.0, 0.0, 0.0);
-                                }
-                            }
-                        }
-                    }
-                    if player.is_jumping && !player.end_jump {
-                        velocity.y = 500.0;
-                        if player.jump_duration.as_secs() <= player.elapsed.elapsed().as_secs() {
-                            player.end_jump = true;
-                        }
-                    }
-
-                    if player.hit {
-                        if player.hit_duration.as_secs() <= player.elapsed_hit.elapsed().as_secs() {
-                            sprite.color = Color::rgb(1.0, 1.0, 1.0);
-                            player.lives -= 1;
-                            player.hit = false;
-                        }
-                    }
-
-                    if player.lives == 0 {
-                        position.0.position.translation.x = 0.0;
-                        position.0.position.translation.y = 0.0;
-                    }
-
-                    if position.0.position.translation.y < -200.0 {
-                        position.0.position.translation.x = 0.0;
-                        position.0.position.translation.y = 0.0;
-                    }
-
-                    velocities.0.linvel = Vec2::new(velocity.x, velocity.y).into();
+    for game_state in game_state_query.iter() {
+        if game_state.state != 1 {
+            sim.accumulator = 0.0;
+            *was_running = false;
+            continue;
+        }
 
-                    velocities.0.angvel = 0.0;
-                }
+        let player_position = sim.simulation.fighter(Fighter::Player).pos;
+        let cursor_aim = gloves_aim(&windows, &camera_query, player_position);
+        let inputs = Fighter::ALL.map(|fighter| {
+            // the right stick wins over the mouse, which only aims for the
+            // player. without either punches aim at the other fighter.
+            let aim = match actions.aim(fighter) {
+                aim if aim != Vec2::ZERO => aim,
+                _ if fighter == Fighter::Player => cursor_aim,
+                _ => Vec2::ZERO,
+            };
+            PlayerInput {
+                left: actions.pressed(fighter, Action::MoveLeft),
+                right: actions.pressed(fighter, Action::MoveRight),
+                jump: actions.pressed(fighter, Action::Jump),
+                attack: actions.pressed(fighter, Action::Punch),
+                block: actions.pressed(fighter, Action::Block),
+                aim,
             }
+            .without_idle_aim()
+        });
+        // the click or button that picked the menu entry, which may also be
+        // punch or jump, doesn't reach the first tick of the match
+        let inputs = if *was_running {
+            inputs
+        } else {
+            [PlayerInput::default(); 2]
+        };
+        *was_running = true;
+
+        sim.accumulator += game_time.delta_seconds();
+        let mut ticks = 0;
+        while sim.accumulator >= sim::DT && ticks < MAX_TICKS_PER_FRAME {
+            sim.accumulator -= sim::DT;
+            ticks += 1;
+            sim.simulation.step(&inputs);
+            sim.inputs.push(inputs);
+            sim_events.send_batch(sim.simulation.events.iter().copied());
+        }
+        if ticks == MAX_TICKS_PER_FRAME {
+            sim.accumulator = 0.0;
         }
     }
 }
 
-fn update_gloves_position(
-    mouse_button: Res<Input<MouseButton>>,
+/// runs the simulation together with the peer. either set of controls
+/// plays the local fighter.
+#[cfg(not(target_arch = "wasm32"))]
+fn update_netplay(
+    time: Res<Time>,
+    actions: Res<ActionState>,
     windows: Res<Windows>,
-    mut gloves_query: Query<
-        (
-            &mut Gloves,
-            &mut RigidBodyPositionComponent,
-            &mut RigidBodyVelocityComponent,
-            Without<Player>,
-        ),
-        With<Gloves>,
-    >,
-    mut camera_query: Query<(&mut Transform, With<Camera>), Without<Gloves>>,
-    player_query: Query<&RigidBodyPositionComponent, With<Player>>,
-    server: Res<AssetServer>,
-    audio: Res<Audio>,
+    camera_query: Query<(&Camera, &Transform)>,
+    mut session: ResMut<netplay::NetSession>,
+    mut sim: ResMut<Sim>,
+    mut sim_events: EventWriter<SimEvent>,
+    mut game_state_query: Query<&mut GameState>,
+    mut winlose_query: Query<&mut Text, With<WinLoseText>>,
 ) {
-    let mut pos_world = Vec4::new(0.0, 0.0, 0.0, 0.0);
-    for (mut gloves, mut position, mut velocities, _) in gloves_query.iter_mut() {
-        for player_position in player_query.iter() {
-            for mut camera_transform in camera_query.iter_mut() {
-                let window = windows.get_primary().unwrap();
-                if let Some(pos) = window.cursor_position() {
-                    let size = Vec2::new(window.width() as f32, window.height() as f32);
-                    let p = pos
-                        - (size / 2.0)
-                        - Vec2::new(
-                            player_position.0.position.translation.x,
-                            player_position.0.position.translation.y,
-                        );
+    let sim = &mut *sim;
+    sim.online = true;
+    let started = session.poll(&mut sim.simulation);
 
-                    pos_world = camera_transform.0.compute_matrix() * p.extend(0.0).extend(1.0);
-                    //println!("{:?}",pos_world);
-                }
-            }
-
-            gloves.point_of_rotation = Point::new(
-                player_position.0.position.translation.x,
-                player_position.0.position.translation.y,
-            );
-
-            if gloves.is_shooting && !gloves.end_shot {
-                velocities.0.linvel.y = pos_world.y;
-                velocities.0.linvel.x = pos_world.x;
-                if gloves.offset.x == 0.0 {
-                    gloves.offset.x += pos_world.x / 100.0;
+    for mut game_state in game_state_query.iter_mut() {
+        for mut win_lose_text in winlose_query.iter_mut() {
+            if started {
+                game_state.state = 1;
+                sim.stage = session.stage().clone();
+                win_lose_text.sections[0].value = "".to_string();
+                win_lose_text.sections[1].value = "".to_string();
+            } else if !session.is_started() {
+                win_lose_text.sections[0].value = match session.local() {
+                    Fighter::Player => " Waiting for player 2".to_string(),
+                    Fighter::Opponent => " Connecting to the host".to_string(),
                 };
-                if gloves.offset.y == 0.0 {
-                    gloves.offset.y += pos_world.y / 100.0;
-                }
-                if gloves.shot_duration.as_millis() <= gloves.elapsed.elapsed().as_millis() {
-                    gloves.end_shot = true;
-                }
-                gloves.offset.x += gloves.offset.x * 0.07;
-                gloves.offset.y += gloves.offset.y * 0.07;
             }
-
-            if gloves.end_shot == true
-                && gloves.shot_duration.as_millis() <= gloves.elapsed.elapsed().as_millis()
-            {
-                gloves.offset.x = 0.0;
-                gloves.offset.y = 0.0;
+            if game_state.state != 1 {
+                continue;
             }
-
-            if gloves.shot_duration.as_millis() <= gloves.elapsed.elapsed().as_millis() {
-                position.0.position.translation.x = player_position.0.position.translation.x;
-                gloves.is_shooting = false;
-                gloves.elapsed = instant::Instant::now();
+            // what only happened once a rollback corrected the prediction
+            sim_events.send_batch(sim.simulation.events.iter().copied());
+
+            let local = session.local();
+            let cursor_aim = gloves_aim(&windows, &camera_query, sim.simulation.fighter(local).pos);
+            let pressed = |action| {
+                Fighter::ALL
+                    .iter()
+                    .any(|fighter| actions.pressed(*fighter, action))
+            };
+            let stick_aim = Fighter::ALL
+                .iter()
+                .map(|fighter| actions.aim(*fighter))
+                .find(|aim| *aim != Vec2::ZERO);
+            let input = PlayerInput {
+                left: pressed(Action::MoveLeft),
+                right: pressed(Action::MoveRight),
+                jump: pressed(Action::Jump),
+                attack: pressed(Action::Punch),
+                block: pressed(Action::Block),
+                aim: stick_aim.unwrap_or(cursor_aim),
             }
-
-            position.0.position.translation.x =
-                player_position.0.position.translation.x + gloves.offset.x;
-            position.0.position.translation.y =
-                player_position.0.position.translation.y + gloves.offset.y;
-
-            if mouse_button.pressed(MouseButton::Left) {
-                if !gloves.is_shooting {
-                    let music: Handle<AudioSource> =
-                        server.load("audio/fist-punch-or-kick-7171.ogg");
-                    audio.play(music);
-                    gloves.elapsed = instant::Instant::now();
-                    gloves.shot_duration = Duration::from_millis(800);
-                    gloves.is_shooting = true;
-                    gloves.end_shot = false;
+            .without_idle_aim();
+
+            sim.accumulator += time.delta_seconds();
+            let mut ticks = 0;
+            while sim.accumulator >= sim::DT && ticks < MAX_TICKS_PER_FRAME {
+                // waiting for the peer to catch up
+                if !session.advance(&mut sim.simulation, input) {
+                    break;
                 }
+                sim.accumulator -= sim::DT;
+                ticks += 1;
+                sim_events.send_batch(sim.simulation.events.iter().copied());
+            }
+            if ticks == MAX_TICKS_PER_FRAME || ticks == 0 {
+                sim.accumulator = sim.accumulator.min(sim::DT);
+            }
+
+            if let Some(winner) = session.confirmed_winner(&sim.simulation) {
+                game_state.state = 0;
+                save_replay(&sim.simulation, &sim.stage, session.confirmed_inputs());
+                win_lose_text.sections[1].value = match winner {
+                    Fighter::Player => " Player Wins!".to_string(),
+                    Fighter::Opponent => " Player 2 Wins!".to_string(),
+                };
             }
         }
     }
 }
 
-fn update_gun_position(
-    mut cmd: Commands,
-    mut gloves_query: Query<
-        (
-            &mut Gun,
-            &mut RigidBodyPositionComponent,
-            &mut RigidBodyVelocityComponent,
-            &mut Sprite,
-        ),
-        (Without<Ai>, With<Gun>, Without<Player>),
-    >,
-    game_state_query: Query<&GameState>,
-    mut player_query: Query<&RigidBodyPositionComponent, (With<Player>, Without<Ai>)>,
-    ai_query: Query<&RigidBodyPositionComponent, (With<Ai>, Without<Player>)>,
-    server: Res<AssetServer>,
-    audio: Res<Audio>,
-) {
-    for (mut gun, mut position, mut velocities, mut sprite) in gloves_query.iter_mut() {
-        for ai_position in ai_query.iter() {
-            for player_position in player_query.iter() {
-                for game_state in game_state_query.iter() {
-                    if game_state.state == 1 {
-                        let rotation_z = f32::atan2(
-                            position.0.position.translation.y
-                                - player_position.0.position.translation.y,
-                            position.0.position.translation.x
-                                - player_position.0.position.translation.x,
-                        );
-                        position.0.position = Isometry2::rotation(rotation_z);
-                        position.0.position.translation.x = ai_position.0.position.translation.x;
-                        position.0.position.translation.y =
-                            ai_position.0.position.translation.y - 10.0;
-                        gun.point_of_rotation = Point::new(0.0, 0.0);
-
-                        if f32::abs(
-                            position.0.position.translation.x
-                                - player_position.0.position.translation.x,
-                        ) >= 400.0
-                        {
-                            gun.is_active = true;
-                        } else {
-                            gun.is_active = false;
-                        }
-
-                        if gun.is_active {
-                            if gun.shot_duration.as_millis() <= gun.elapsed.elapsed().as_millis() {
-                                gun.shot_duration = Duration::from_millis(569);
-                                gun.elapsed = instant::Instant::now();
-                                sprite.color = Color::rgba(1.0, 1.0, 1.0, 1.0);
-                                gun.do_shot = true;
-                            }
-                        } else {
-                            sprite.color = Color::rgba(1.0, 1.0, 1.0, 0.0);
-                            gun.is_active = false;
-                        }
-
-                        if gun.do_shot {
-                            let music: Handle<AudioSource> =
-                                server.load("audio/9mm-pistol-shot-6349.ogg");
-                            audio.play(music);
-                            gun.do_shot = false;
-                            let bullet = SpriteBundle {
-                                texture: server.load("bullet.png"),
-                                transform: Transform {
-                                    translation: Vec3::new(0.0, 0.0, 3.0),
-                                    scale: Vec3::new(32.0, 32.0, 0.0),
-                                    ..Default::default()
-                                },
-                                sprite: Sprite {
-                                    color: Color::rgb(1.0, 1.0, 1.0),
-                                    custom_size: Some(Vec2::new(0.5, 0.5)),
-                                    ..Default::default()
-                                },
-                                ..Default::default()
-                            };
+fn save_replay(simulation: &Simulation, stage: &Stage, inputs: Vec<[PlayerInput; 2]>) {
+    let replay = Replay {
+        seed: simulation.seed,
+        settings: simulation.settings,
+        stage: stage.clone(),
+        inputs,
+    };
+    if let Err(err) = replay.save() {
+        warn!("could not save the replay: {}", err);
+    }
+}
 
-                            let bullet_rigid_body = RigidBodyBundle {
-                                body_type: RigidBodyTypeComponent(
-                                    RigidBodyType::KinematicVelocityBased,
-                                ),
-                                position: Vec2::new(
-                                    position.0.position.translation.x,
-                                    position.0.position.translation.y,
-                                )
-                                .into(),
-                                velocity: RigidBodyVelocity {
-                                    linvel: Vec2::new(0.0, 0.0).into(),
-                                    angvel: 0.0,
-                                }
-                                .into(),
-                                activation: RigidBodyActivation::cannot_sleep().into(),
-                                ccd: RigidBodyCcd {
-                                    ccd_enabled: true,
-                                    ..Default::default()
-                                }
-                                .into(),
-                                ..Default::default()
-                            };
+/// where the cursor points to, relative to the player. zero without a
+/// cursor over the window, so punches aim at the other fighter.
+fn gloves_aim(
+    windows: &Windows,
+    camera_query: &Query<(&Camera, &Transform)>,
+    player_position: Vec2,
+) -> Vec2 {
+    camera::cursor_to_world(windows, camera_query.iter())
+        .map_or(Vec2::ZERO, |pos| pos - player_position)
+}
 
-                            cmd.spawn_bundle(bullet)
-                                .insert_bundle(bullet_rigid_body)
-                                .insert(Collider::Bullet)
-                                .insert(Bullet {
-                                    lifetime: Duration::new(4, 0),
-                                    shoot_dir: Vec2::new(
-                                        position.0.position.translation.x
-                                            - player_position.position.translation.x,
-                                        position.0.position.translation.y
-                                            - player_position.position.translation.y,
-                                    ),
-                                    elapsed: instant::Instant::now(),
-                                })
-                                .insert(RigidBodyPositionSync::Discrete);
-                        }
-                    }
-                }
-            }
-        }
+fn fighter_image(role: Role) -> &'static str {
+    match role {
+        Role::Boxer => "boxer.png",
+        Role::WeaponSpecialist => "weapon_specialist.png",
     }
 }
 
-fn update_shotgun_position(
-    mut cmd: Commands,
-    mut gloves_query: Query<
-        (
-            &mut Shotgun,
-            &mut RigidBodyPositionComponent,
-            &mut RigidBodyVelocityComponent,
-            &mut Sprite,
-        ),
-        (Without<Ai>, With<Shotgun>, Without<Player>),
-    >,
-    game_state_query: Query<&GameState>,
-    player_query: Query<&RigidBodyPositionComponent, (With<Player>, Without<Ai>)>,
-    ai_query: Query<&RigidBodyPositionComponent, (With<Ai>, Without<Player>)>,
-    server: Res<AssetServer>,
-    audio: Res<Audio>,
+fn update_body_sprites(
+    sim: Res<Sim>,
+    mut body_query: Query<(&Body, &mut Transform, &mut TextureAtlasSprite)>,
 ) {
-    for (mut gun, mut position, mut velocities, mut sprite) in gloves_query.iter_mut() {
-        for ai_position in ai_query.iter() {
-            for player_position in player_query.iter() {
-                for game_state in game_state_query.iter() {
-                    if game_state.state == 1 {
-                        let rotation_z = f32::atan2(
-                            position.0.position.translation.y
-                                - player_position.0.position.translation.y,
-                            position.0.position.translation.x
-                                - player_position.0.position.translation.x,
-                        );
-                        position.0.position = Isometry2::rotation(rotation_z);
-                        position.0.position.translation.x = ai_position.0.position.translation.x;
-                        position.0.position.translation.y =
-                            ai_position.0.position.translation.y - 10.0;
-
-                        if f32::abs(
-                            position.0.position.translation.x
-                                - player_position.0.position.translation.x,
-                        ) <= 400.0
-                        {
-                            gun.is_active = true;
-                        } else {
-                            gun.is_active = false;
-                        }
-
-                        if gun.is_active {
-                            if gun.shot_duration.as_secs() <= gun.elapsed.elapsed().as_secs() {
-                                gun.do_shot = true;
-                                gun.shot_duration = Duration::new(5, 0);
-                                gun.elapsed = instant::Instant::now();
-                            }
-                            sprite.color = Color::rgba(1.0, 1.0, 1.0, 1.0);
-                        } else {
-                            sprite.color = Color::rgba(1.0, 1.0, 1.0, 0.0);
-                        }
-                        if gun.do_shot {
-                            let music: Handle<AudioSource> =
-                                server.load("audio/9mm-pistol-shot-6349.ogg");
-                            audio.play(music);
-
-                            /* creating gloves */
-                            let bullet = SpriteBundle {
-                                texture: server.load("bullet.png"),
-                                transform: Transform {
-                                    translation: Vec3::new(0.0, 0.0, 3.0),
-                                    scale: Vec3::new(32.0, 32.0, 0.0),
-                                    ..Default::default()
-                                },
-                                sprite: Sprite {
-                                    color: Color::rgb(1.0, 1.0, 1.0),
-                                    custom_size: Some(Vec2::new(0.5, 0.5)),
-                                    ..Default::default()
-                                },
-                                ..Default::default()
-                            };
-
-                            let bullet_rigid_body = RigidBodyBundle {
-                                body_type: RigidBodyTypeComponent(
-                                    RigidBodyType::KinematicVelocityBased,
-                                ),
-                                position: Vec2::new(
-                                    position.0.position.translation.x,
-                                    position.0.position.translation.y,
-                                )
-                                .into(),
-                                velocity: RigidBodyVelocity {
-                                    linvel: Vec2::new(0.0, 0.0).into(),
-                                    angvel: 0.0,
-                                }
-                                .into(),
-                                activation: RigidBodyActivation::cannot_sleep().into(),
-                                ccd: RigidBodyCcd {
-                                    ccd_enabled: true,
-                                    ..Default::default()
-                                }
-                                .into(),
-                                ..Default::default()
-                            };
-
-                            let bullet2 = SpriteBundle {
-                                texture: server.load("bullet.png"),
-                                transform: Transform {
-                                    translation: Vec3::new(0.0, 0.0, 3.0),
-                                    scale: Vec3::new(32.0, 32.0, 0.0),
-                                    ..Default::default()
-                                },
-                                sprite: Sprite {
-                                    color: Color::rgb(1.0, 1.0, 1.0),
-                                    custom_size: Some(Vec2::new(0.5, 0.5)),
-                                    ..Default::default()
-                                },
-                                ..Default::default()
-                            };
-
-                            let bullet2_rigid_body = RigidBodyBundle {
-                                body_type: RigidBodyTypeComponent(
-                                    RigidBodyType::KinematicVelocityBased,
-                                ),
-                                position: Vec2::new(
-                                    position.0.position.translation.x,
-                                    position.0.position.translation.y,
-                                )
-                                .into(),
-                                velocity: RigidBodyVelocity {
-                                    linvel: Vec2::new(0.0, 0.0).into(),
-                                    angvel: 0.0,
-                                }
-                                .into(),
-                                activation: RigidBodyActivation::cannot_sleep().into(),
-                                ccd: RigidBodyCcd {
-                                    ccd_enabled: true,
-                                    ..Default::default()
-                                }
-                                .into(),
-                                ..Default::default()
-                            };
-
-                            let bullet3 = SpriteBundle {
-                                texture: server.load("bullet.png"),
-                                transform: Transform {
-                                    translation: Vec3::new(0.0, 0.0, 3.0),
-                                    scale: Vec3::new(32.0, 32.0, 0.0),
-                                    ..Default::default()
-                                },
-                                sprite: Sprite {
-                                    color: Color::rgb(1.0, 1.0, 1.0),
-                                    custom_size: Some(Vec2::new(0.5, 0.5)),
-                                    ..Default::default()
-                                },
-                                ..Default::default()
-                            };
-
-                            let bullet3_rigid_body = RigidBodyBundle {
-                                body_type: RigidBodyTypeComponent(
-                                    RigidBodyType::KinematicVelocityBased,
-                                ),
-                                position: Vec2::new(
-                                    position.0.position.translation.x,
-                                    position.0.position.translation.y,
-                                )
-                                .into(),
-                                velocity: RigidBodyVelocity {
-                                    linvel: Vec2::new(0.0, 0.0).into(),
-                                    angvel: 0.0,
-                                }
-                                .into(),
-                                activation: RigidBodyActivation::cannot_sleep().into(),
-                                ccd: RigidBodyCcd {
-                                    ccd_enabled: true,
-                                    ..Default::default()
-                                }
-                                .into(),
-                                ..Default::default()
-                            };
-                            cmd.spawn_bundle(bullet)
-                                .insert_bundle(bullet_rigid_body)
-                                .insert(Collider::Bullet)
-                                .insert(Bullet {
-                                    lifetime: Duration::new(4, 0),
-                                    shoot_dir: Vec2::new(
-                                        position.0.position.translation.x
-                                            - player_position.position.translation.x,
-                                        position.0.position.translation.y
-                                            - player_position.position.translation.y,
-                                    ),
-                                    elapsed: instant::Instant::now(),
-                                })
-                                .insert(RigidBodyPositionSync::Discrete);
-
-                            cmd.spawn_bundle(bullet2)
-                                .insert_bundle(bullet2_rigid_body)
-                                .insert(Collider::Bullet)
-                                .insert(Bullet {
-                                    lifetime: Duration::new(4, 0),
-                                    shoot_dir: Vec2::new(
-                                        position.0.position.translation.x
-                                            - player_position.position.translation.x,
-                                        position.0.position.translation.y
-                                            - player_position.position.translation.y * 0.5,
-                                    ),
-                                    elapsed: instant::Instant::now(),
-                                })
-                                .insert(RigidBodyPositionSync::Discrete);
-                            cmd.spawn_bundle(bullet3)
-                                .insert_bundle(bullet3_rigid_body)
-                                .insert(Collider::Bullet)
-                                .insert(Bullet {
-                                    lifetime: Duration::new(4, 0),
-                                    shoot_dir: Vec2::new(
-                                        position.0.position.translation.x
-                                            - player_position.position.translation.x,
-                                        position.0.position.translation.y
-                                            - player_position.position.translation.y * 2.0,
-                                    ),
-                                    elapsed: instant::Instant::now(),
-                                })
-                                .insert(RigidBodyPositionSync::Discrete);
-                            gun.do_shot = false;
-                        }
-                    }
-                }
-            }
-        }
+    for (body, mut transform, mut sprite) in body_query.iter_mut() {
+        let fighter = sim.simulation.fighter(body.fighter);
+        transform.translation.x = fighter.pos.x;
+        transform.translation.y = fighter.pos.y;
+        sprite.color = if fighter.lives == 0 {
+            Color::rgb(0.5, 0.5, 0.5)
+        } else if fighter.is_hit() {
+            Color::rgb(1.0, 0.0, 0.0)
+        } else if fighter.blocking {
+            Color::rgb(0.6, 0.6, 1.0)
+        } else {
+            Color::rgb(1.0, 1.0, 1.0)
+        };
     }
 }
 
-fn update_bullets(
-    mut cmd: Commands,
-    player_query: Query<(&RigidBodyPositionComponent), (With<Player>, Without<Bullet>)>,
-    mut bullet_query: Query<
-        (Entity, &mut Bullet, &mut RigidBodyPositionComponent),
-        (With<Bullet>, Without<Player>),
-    >,
+fn update_gloves_sprites(
+    sim: Res<Sim>,
+    mut gloves_query: Query<(&Gloves, &mut Transform, &mut Sprite)>,
 ) {
-    for (Entity, mut class, mut bullet) in bullet_query.iter_mut() {
-        for player_position in player_query.iter() {
-            bullet.0.position.translation.x += -class.shoot_dir.normalize().x * 6.0;
-            bullet.0.position.translation.y += -class.shoot_dir.normalize().y * 6.0;
-            if class.lifetime.as_secs() <= class.elapsed.elapsed().as_secs() {
-                cmd.entity(Entity).despawn();
-            }
-        }
+    for (gloves, mut transform, mut sprite) in gloves_query.iter_mut() {
+        let fighter = sim.simulation.fighter(gloves.fighter);
+        transform.translation.x = fighter.gloves.pos.x;
+        transform.translation.y = fighter.gloves.pos.y;
+        sprite.flip_x = fighter.facing < 0.0;
+        sprite.color = if fighter.role == Role::Boxer {
+            Color::rgba(1.0, 1.0, 1.0, 1.0)
+        } else {
+            Color::rgba(1.0, 1.0, 1.0, 0.0)
+        };
     }
 }
 
-fn update_ai_movement(
-    mut player_query: Query<
-        (
-            &mut Ai,
-            &RigidBodyPositionComponent,
-            &mut RigidBodyVelocityComponent,
-            &mut Sprite,
-        ),
-        With<Ai>,
-    >,
-    game_state_query: Query<&GameState>,
-    collider_query: Query<(Entity, &Collider, &RigidBodyPositionComponent), Without<Ai>>,
-    gloves_query: Query<
-        (&RigidBodyPositionComponent),
-        (With<Gloves>, Without<Ai>, Without<Collider>),
-    >,
-    server: Res<AssetServer>,
-    audio: Res<Audio>,
+fn update_weapon_sprite(
+    fighter: &sim::FighterState,
+    weapon: &sim::WeaponState,
+    transform: &mut Transform,
+    sprite: &mut Sprite,
 ) {
-    for (mut player, position, mut velocities, mut sprite) in player_query.iter_mut() {
-        let mut velocity = Vec2::new(velocities.0.linvel.x, velocities.0.linvel.y - 500.0);
-        for (entity, collider, transform) in collider_query.iter() {
-            for game_state in game_state_query.iter() {
-                if game_state.state == 1 {
-                    let action = rand::thread_rng().gen_range(0..2);
-                    let action_jump = rand::thread_rng().gen_range(0..800);
-                    let collision = collide(
-                        Vec3::new(
-                            position.0.position.translation.x,
-                            position.0.position.translation.y - 15.0, // y_offset
-                            0.0,
-                        ),
-                        Vec2::new(64.0, 128.0),
-                        Vec3::new(
-                            transform.0.position.translation.x,
-                            transform.0.position.translation.y,
-                            0.0,
-                        ),
-                        if matches!(collider, Collider::Solid) {
-                            Vec2::new(700.0, 50.0)
-                        } else {
-                            Vec2::new(32.0, 32.0)
-                        },
-                    );
-
-                    if let Some(collision) = collision {
-                        if let Collider::Solid = *collider {
-                            match collision {
-                                Collision::Top => {
-                                    velocity.y = 0.0;
-                                    player.jump_end = false;
-                                }
-                                _ => {}
-                            }
-                        }
-                        if let Collider::Gloves = *collider {
-                            match collision {
-                                _ => {
-                                    if player.lives > 0 {
-                                        if !player.hit {
-                                            let music: Handle<AudioSource> =
-                                                server.load("audio/hit-someting-6037.ogg");
-                                            audio.play(music);
-                                            player.hit_duration = Duration::new(3, 0);
-                                            player.hit = true;
-                                            sprite.color = Color::rgb(1.0, 0.0, 0.0);
-                                            player.elapsed_hit = instant::Instant::now();
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    player.walk_x = action;
-                    player.jump_y = action_jump;
-                    if player.walk_x == 1 && position.0.position.translation.x <= 300.0 {
-                        velocity.x = 200.0;
-                    } else if (player.walk_x == 0 && position.0.position.translation.x >= -500.0) {
-                        velocity.x = -200.0;
-                    }
-
-                    if player.jump_y == 1 {
-                        player.jump = true;
-                    }
-
-                    if player.jump == true {
-                        if player.jump_duration.as_millis()
-                            > player.elapsed_jump.elapsed().as_millis()
-                        {
-                            if !player.jump_end {
-                                velocity.y = 500.0;
-                            }
-                        } else {
-                            player.jump_end = true;
-                            player.jump = false;
-                        }
-                    }
-
-                    if player.jump_end {
-                        player.elapsed_jump = instant::Instant::now();
-                    }
-
-                    if player.hit && player.lives > 0 {
-                        if player.hit_duration.as_secs() <= player.elapsed_hit.elapsed().as_secs() {
-                            player.hit = false;
-                            sprite.color = Color::rgb(1.0, 1.0, 1.0);
-                            player.lives -= 1;
-                        }
-                    }
-
-                    if player.lives == 0 {
-                        sprite.color = Color::rgba(0.0, 0.0, 0.0, 0.0)
-                    }
-
-                    velocities.0.linvel = Vec2::new(velocity.x, velocity.y).into();
+    transform.translation.x = weapon.pos.x;
+    transform.translation.y = weapon.pos.y;
+    transform.rotation = Quat::from_rotation_z(weapon.angle);
+    // the images point left, turned around they would be upside down
+    sprite.flip_y = fighter.facing > 0.0;
+    sprite.color = if weapon.is_active {
+        Color::rgba(1.0, 1.0, 1.0, 1.0)
+    } else {
+        Color::rgba(1.0, 1.0, 1.0, 0.0)
+    };
+}
 
-                    velocities.0.angvel = 0.0;
-                }
-            }
-        }
+fn update_gun_sprites(sim: Res<Sim>, mut gun_query: Query<(&Gun, &mut Transform, &mut Sprite)>) {
+    for (gun, mut transform, mut sprite) in gun_query.iter_mut() {
+        let fighter = sim.simulation.fighter(gun.fighter);
+        update_weapon_sprite(fighter, &fighter.gun, &mut transform, &mut sprite);
     }
 }
 
-fn player_lifes_update_system(
-    mut query: Query<&mut Text, With<PlayerText>>,
-    mut player_query: Query<&mut Player>,
+fn update_shotgun_sprites(
+    sim: Res<Sim>,
+    mut shotgun_query: Query<(&Shotgun, &mut Transform, &mut Sprite)>,
 ) {
-    for mut text in query.iter_mut() {
-        for mut player in player_query.iter() {
-            // Update the value of the second section
-            text.sections[1].value = format!(" {}", player.lives);
-        }
+    for (shotgun, mut transform, mut sprite) in shotgun_query.iter_mut() {
+        let fighter = sim.simulation.fighter(shotgun.fighter);
+        update_weapon_sprite(fighter, &fighter.shotgun, &mut transform, &mut sprite);
     }
 }
-fn enemy_lifes_update_system(
-    mut query: Query<&mut Text, With<AiText>>,
-    mut player_query: Query<&mut Ai>,
+
+/// shows every bullet of the simulation with a sprite from the pool and
+/// puts the sprites of bullets that are gone back.
+fn update_bullets(
+    mut cmd: Commands,
+    sim: Res<Sim>,
+    mut pool: ResMut<BulletPool>,
+    mut bullet_query: Query<(Entity, &mut Bullet, &mut Transform, &mut Visibility)>,
 ) {
-    for mut text in query.iter_mut() {
-        for mut player in player_query.iter() {
-            // Update the value of the second section
-            text.sections[1].value = format!(" {}", player.lives);
+    let mut new_bullets: HashMap<u32, Vec2> = sim
+        .simulation
+        .bullets
+        .iter()
+        .map(|state| (state.id, state.pos))
+        .collect();
+    for (entity, mut bullet, mut transform, mut visibility) in bullet_query.iter_mut() {
+        let id = match bullet.id {
+            Some(id) => id,
+            None => continue,
+        };
+        match new_bullets.remove(&id) {
+            Some(pos) => {
+                transform.translation.x = pos.x;
+                transform.translation.y = pos.y;
+            }
+            None => {
+                bullet.id = None;
+                visibility.is_visible = false;
+                pool.free.push(entity);
+            }
+        }
+    }
+
+    for (id, pos) in new_bullets {
+        let translation = pos.extend(3.0);
+        match pool.free.pop() {
+            Some(entity) => {
+                // the sprite was hidden when it went back into the pool
+                if let Ok((_, mut bullet, mut transform, mut visibility)) =
+                    bullet_query.get_mut(entity)
+                {
+                    bullet.id = Some(id);
+                    transform.translation = translation;
+                    visibility.is_visible = true;
+                }
+            }
+            None => {
+                let bullet = SpriteBundle {
+                    texture: pool.texture.clone(),
+                    transform: Transform::from_translation(translation),
+                    sprite: Sprite {
+                        color: Color::rgb(1.0, 1.0, 1.0),
+                        custom_size: Some(BULLET_SIZE),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                cmd.spawn_bundle(bullet).insert(Bullet { id: Some(id) });
+            }
         }
     }
 }
 
 fn update_game_state(
-    mouse_button: Res<Input<MouseButton>>,
+    actions: Res<ActionState>,
+    input_map: Res<InputMap>,
+    menu: Res<ControlsMenu>,
+    mut settings: ResMut<Settings>,
+    mut sim: ResMut<Sim>,
     mut game_state_query: Query<&mut GameState>,
-    mut player_query: Query<&mut Player>,
-    mut Ai: Query<(&mut Ai, &mut Sprite)>,
-    mut Winlose_query: Query<&mut Text, With<WinLoseText>>,
+    mut winlose_query: Query<&mut Text, With<WinLoseText>>,
+    mut last_state: Local<i32>,
 ) {
+    if sim.online {
+        return;
+    }
+    let opponent_control = sim.simulation.settings.opponent_control;
     for mut game_state in game_state_query.iter_mut() {
-        for mut player in player_query.iter_mut() {
-            for (mut ai, mut ai_sprite) in Ai.iter_mut() {
-                for mut win_lose_text in Winlose_query.iter_mut() {
-                    if game_state.state == 0 {
-                        win_lose_text.sections[0].value = format!(" Left click to start");
-                        if mouse_button.just_pressed(MouseButton::Left) {
-                            game_state.state = 1;
-                            win_lose_text.sections[1].value = format!("");
-                            win_lose_text.sections[0].value = format!("");
-                        }
-                    }
-                    if player.lives == 0 {
-                        game_state.state = 0;
-                        player.lives = 3;
-                        ai.lives = 10;
-                        win_lose_text.sections[1].value = format!(" Enemy Wins!");
-                    }
-                    if ai.lives == 0 {
-                        game_state.state = 0;
-                        player.lives = 3;
-                        ai.lives = 10;
-                        win_lose_text.sections[1].value = format!(" Player Wins!");
-                        ai_sprite.color = Color::rgba(1.0, 1.0, 1.0, 1.0)
-                    }
+        // start picks a menu entry and pauses, the press that started the
+        // match this frame doesn't also pause it
+        let started = game_state.state != *last_state;
+        for mut win_lose_text in winlose_query.iter_mut() {
+            if game_state.state == 0 && menu.open {
+                win_lose_text.sections[0].value = "".to_string();
+            } else if game_state.state == 0 {
+                win_lose_text.sections[0].value = format!(
+                    " {} for the stage: {}, E to edit it",
+                    input_map.names(Fighter::Player, Action::NextStage),
+                    sim.stage.name
+                );
+                if actions.any_just_pressed(Action::NextStage) {
+                    sim.stage = sim.stage.next();
+                    settings.stage = sim.stage.id.clone();
+                    let settings = sim.simulation.settings;
+                    sim.simulation =
+                        Simulation::with_arena(rand::random(), settings, sim.stage.arena.clone());
+                }
+            } else if game_state.state == 2 && actions.any_just_pressed(Action::QuitToTitle) {
+                // back to the title without a winner, nor a replay
+                game_state.state = 0;
+                win_lose_text.sections[0].value = "".to_string();
+                sim.inputs.clear();
+                let settings = sim.simulation.settings;
+                sim.simulation =
+                    Simulation::with_arena(rand::random(), settings, sim.stage.arena.clone());
+            } else if actions.any_just_pressed(Action::Pause) && !started {
+                if game_state.state == 1 {
+                    game_state.state = 2;
+                    win_lose_text.sections[0].value = format!(
+                        " Paused, {} to quit to the title",
+                        input_map.names(Fighter::Player, Action::QuitToTitle)
+                    );
+                } else if game_state.state == 2 {
+                    game_state.state = 1;
+                    win_lose_text.sections[0].value = "".to_string();
                 }
             }
+            if game_state.state == 3 || game_state.state == 4 {
+                win_lose_text.sections[0].value = "".to_string();
+                win_lose_text.sections[1].value = "".to_string();
+            } else if let Some(winner) = sim.simulation.winner() {
+                game_state.state = 0;
+                let inputs = std::mem::take(&mut sim.inputs);
+                save_replay(&sim.simulation, &sim.stage, inputs);
+                win_lose_text.sections[1].value = match (winner, opponent_control) {
+                    (Fighter::Player, _) => " Player Wins!".to_string(),
+                    (Fighter::Opponent, Control::Human) => " Player 2 Wins!".to_string(),
+                    (Fighter::Opponent, Control::Ai) => " Enemy Wins!".to_string(),
+                    (Fighter::Opponent, Control::Dummy) => " Training Over".to_string(),
+                };
+                let settings = sim.simulation.settings;
+                sim.simulation =
+                    Simulation::with_arena(rand::random(), settings, sim.stage.arena.clone());
+            }
         }
+        *last_state = game_state.state;
     }
 }
@@ -0,0 +1,412 @@
+//! The main menu on the title screen.
+//!
+//! It starts a match against the ai, a local versus match or a training
+//! match against a dummy, watches the latest replay, opens the settings or
+//! quits. The settings change the volumes, the window mode and the
+//! difficulty of the ai and open the controls menu.
+//!
+//! Up and down or the mouse select an entry, return, space or a click picks
+//! it, left and right change the value of an entry and escape goes back. On
+//! a gamepad the d-pad moves, the south button or start picks and the east
+//! button goes back.
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+
+use crate::controls_menu::ControlsMenu;
+use crate::gamepad::{self, GamepadLobby};
+use crate::hud::ScaledText;
+use crate::replay::{Replay, ReplayViewer};
+use crate::settings::{self, Settings, WINDOW_MODES};
+use crate::sim::{Control, Difficulty, MatchSettings, Role, Simulation};
+use crate::{GameState, Sim, WinLoseText};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Entry {
+    PlayAi,
+    Versus,
+    Training,
+    Replays,
+    Settings,
+    Quit,
+    MasterVolume,
+    EffectsVolume,
+    MusicVolume,
+    WindowMode,
+    Difficulty,
+    Controls,
+    Back,
+}
+
+const MAIN: &[Entry] = &[
+    Entry::PlayAi,
+    Entry::Versus,
+    Entry::Training,
+    Entry::Replays,
+    Entry::Settings,
+    Entry::Quit,
+];
+
+const SETTINGS: &[Entry] = &[
+    Entry::MasterVolume,
+    Entry::EffectsVolume,
+    Entry::MusicVolume,
+    Entry::WindowMode,
+    Entry::Difficulty,
+    Entry::Controls,
+    Entry::Back,
+];
+
+// enough buttons for the longer of the two screens
+const BUTTONS: usize = 7;
+
+pub struct MainMenu {
+    settings_open: bool,
+    selected: usize,
+    /// who the versus and training matches are against
+    opponent_role: Role,
+}
+
+impl Default for MainMenu {
+    fn default() -> Self {
+        MainMenu {
+            settings_open: false,
+            selected: 0,
+            opponent_role: Role::WeaponSpecialist,
+        }
+    }
+}
+
+impl MainMenu {
+    fn entries(&self) -> &'static [Entry] {
+        if self.settings_open {
+            SETTINGS
+        } else {
+            MAIN
+        }
+    }
+
+    fn label(&self, entry: Entry, settings: &Settings) -> String {
+        let role = match self.opponent_role {
+            Role::Boxer => "Boxer",
+            Role::WeaponSpecialist => "Weapon Specialist",
+        };
+        let volume = |volume: f32| format!("{:.0}%", volume * 100.0);
+        match entry {
+            Entry::PlayAi => "Play vs AI".to_string(),
+            Entry::Versus => format!("Local Versus  < {} >", role),
+            Entry::Training => format!("Training  < {} >", role),
+            Entry::Replays => "Latest Replay".to_string(),
+            Entry::Settings => "Settings".to_string(),
+            Entry::Quit => "Quit".to_string(),
+            Entry::MasterVolume => {
+                format!("Master Volume  < {} >", volume(settings.master_volume))
+            }
+            Entry::EffectsVolume => {
+                format!("Effects Volume  < {} >", volume(settings.effects_volume))
+            }
+            Entry::MusicVolume => {
+                format!("Music Volume  < {} >", volume(settings.music_volume))
+            }
+            Entry::WindowMode => format!(
+                "Window  < {} >",
+                settings::window_mode_label(settings.window_mode)
+            ),
+            Entry::Difficulty => format!("Difficulty  < {} >", settings.difficulty.label()),
+            Entry::Controls => "Controls".to_string(),
+            Entry::Back => "Back".to_string(),
+        }
+    }
+
+    /// changes the value of `entry` by `steps`, if it has one.
+    fn change(&mut self, entry: Entry, steps: i32, settings: &mut Settings) {
+        match entry {
+            Entry::Versus | Entry::Training => {
+                self.opponent_role = match self.opponent_role {
+                    Role::Boxer => Role::WeaponSpecialist,
+                    Role::WeaponSpecialist => Role::Boxer,
+                };
+            }
+            Entry::MasterVolume => {
+                settings.master_volume = settings::step_volume(settings.master_volume, steps)
+            }
+            Entry::EffectsVolume => {
+                settings.effects_volume = settings::step_volume(settings.effects_volume, steps)
+            }
+            Entry::MusicVolume => {
+                settings.music_volume = settings::step_volume(settings.music_volume, steps)
+            }
+            Entry::WindowMode => {
+                let index = WINDOW_MODES
+                    .iter()
+                    .position(|mode| *mode == settings.window_mode)
+                    .unwrap_or(0);
+                settings.window_mode = WINDOW_MODES[cycle(index, steps, WINDOW_MODES.len())];
+            }
+            Entry::Difficulty => {
+                let index = Difficulty::ALL
+                    .iter()
+                    .position(|difficulty| *difficulty == settings.difficulty)
+                    .unwrap_or(0);
+                settings.difficulty = Difficulty::ALL[cycle(index, steps, Difficulty::ALL.len())];
+            }
+            _ => (),
+        }
+    }
+
+    fn open(&mut self, settings_open: bool) {
+        self.settings_open = settings_open;
+        self.selected = 0;
+    }
+}
+
+/// `index` moved by `steps`, wrapping around at `len`.
+fn cycle(index: usize, steps: i32, len: usize) -> usize {
+    (index as i32 + steps).rem_euclid(len as i32) as usize
+}
+
+#[derive(Component)]
+pub struct MenuRoot;
+
+#[derive(Component)]
+pub struct MenuButton {
+    index: usize,
+}
+
+#[derive(Component)]
+pub struct MenuText {
+    index: usize,
+}
+
+pub fn setup_main_menu(mut commands: Commands, server: Res<AssetServer>) {
+    let font = server.load("fonts/IceCaps.ttf");
+    let text = |size: f32| TextBundle {
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: font.clone(),
+                font_size: size,
+                color: Color::WHITE,
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                // from the top down, bevy's ui has y going up
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: UiColor(Color::rgba(0.0, 0.0, 0.0, 0.0)),
+            ..Default::default()
+        })
+        .insert(MenuRoot)
+        .with_children(|parent| {
+            let mut title = text(96.0);
+            title.text.sections[0].value = "DUEL".to_string();
+            title.style.margin = Rect {
+                top: Val::Percent(1.0),
+                bottom: Val::Percent(1.0),
+                ..Default::default()
+            };
+            parent.spawn_bundle(title).insert(ScaledText(96.0));
+            for index in 0..BUTTONS {
+                parent
+                    .spawn_bundle(ButtonBundle {
+                        style: Style {
+                            padding: Rect {
+                                left: Val::Px(16.0),
+                                right: Val::Px(16.0),
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        },
+                        color: UiColor(Color::rgba(0.0, 0.0, 0.0, 0.0)),
+                        ..Default::default()
+                    })
+                    .insert(MenuButton { index })
+                    .with_children(|button| {
+                        button
+                            .spawn_bundle(text(40.0))
+                            .insert(ScaledText(40.0))
+                            .insert(MenuText { index });
+                    });
+            }
+        });
+}
+
+/// the menu is up on the title screen while no other menu is.
+fn is_shown<'a>(
+    mut game_states: impl Iterator<Item = &'a GameState>,
+    sim: &Sim,
+    controls_menu: &ControlsMenu,
+) -> bool {
+    !sim.online && !controls_menu.open && game_states.any(|game_state| game_state.state == 0)
+}
+
+pub fn update_main_menu(
+    keyboard_input: Res<Input<KeyCode>>,
+    lobby: Res<GamepadLobby>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut controls_menu: ResMut<ControlsMenu>,
+    mut menu: ResMut<MainMenu>,
+    mut settings: ResMut<Settings>,
+    mut viewer: ResMut<ReplayViewer>,
+    mut sim: ResMut<Sim>,
+    mut exit: EventWriter<AppExit>,
+    button_query: Query<(&MenuButton, &Interaction), Changed<Interaction>>,
+    mut game_state_query: Query<&mut GameState>,
+    mut win_lose_query: Query<&mut Text, With<WinLoseText>>,
+) {
+    // the escape that closes the controls menu shouldn't also go back here
+    let shown = is_shown(game_state_query.iter(), &sim, &controls_menu);
+    if !shown || controls_menu.is_changed() {
+        return;
+    }
+    let pressed = |key: KeyCode, button: GamepadButtonType| {
+        keyboard_input.just_pressed(key) || gamepad::just_pressed(&lobby, &gamepad_buttons, button)
+    };
+    let entries = menu.entries();
+
+    let mut picked = pressed(KeyCode::Return, GamepadButtonType::South)
+        || keyboard_input.just_pressed(KeyCode::Space)
+        || gamepad::start_pressed(&lobby, &gamepad_buttons);
+    let mut steps = 0;
+    if pressed(KeyCode::Up, GamepadButtonType::DPadUp) {
+        menu.selected = cycle(menu.selected, -1, entries.len());
+    } else if pressed(KeyCode::Down, GamepadButtonType::DPadDown) {
+        menu.selected = cycle(menu.selected, 1, entries.len());
+    } else if pressed(KeyCode::Left, GamepadButtonType::DPadLeft) {
+        steps = -1;
+    } else if pressed(KeyCode::Right, GamepadButtonType::DPadRight) {
+        steps = 1;
+    } else if pressed(KeyCode::Escape, GamepadButtonType::East) && menu.settings_open {
+        menu.open(false);
+        return;
+    }
+    for (button, interaction) in button_query.iter() {
+        if button.index >= entries.len() {
+            continue;
+        }
+        match interaction {
+            Interaction::Hovered => menu.selected = button.index,
+            Interaction::Clicked => {
+                menu.selected = button.index;
+                picked = true;
+            }
+            Interaction::None => (),
+        }
+    }
+
+    let entry = entries[menu.selected.min(entries.len() - 1)];
+    if steps != 0 {
+        menu.change(entry, steps, &mut settings);
+        return;
+    }
+    if !picked {
+        return;
+    }
+    let match_settings = match entry {
+        Entry::PlayAi => MatchSettings {
+            opponent_role: Role::WeaponSpecialist,
+            opponent_control: Control::Ai,
+            difficulty: settings.difficulty,
+        },
+        Entry::Versus => MatchSettings {
+            opponent_role: menu.opponent_role,
+            opponent_control: Control::Human,
+            ..Default::default()
+        },
+        Entry::Training => MatchSettings {
+            opponent_role: menu.opponent_role,
+            opponent_control: Control::Dummy,
+            ..Default::default()
+        },
+        Entry::Replays => {
+            match Replay::load_latest() {
+                Ok(replay) => viewer.open(replay),
+                Err(err) => warn!("could not load a replay: {}", err),
+            }
+            return;
+        }
+        Entry::Settings => {
+            menu.open(true);
+            return;
+        }
+        Entry::Back => {
+            menu.open(false);
+            return;
+        }
+        Entry::Controls => {
+            controls_menu.open = true;
+            return;
+        }
+        Entry::Quit => {
+            exit.send(AppExit);
+            return;
+        }
+        _ => {
+            // picking a value steps through them
+            menu.change(entry, 1, &mut settings);
+            return;
+        }
+    };
+
+    for mut game_state in game_state_query.iter_mut() {
+        game_state.state = 1;
+    }
+    for mut text in win_lose_query.iter_mut() {
+        for section in text.sections.iter_mut() {
+            section.value = String::new();
+        }
+    }
+    sim.simulation =
+        Simulation::with_arena(rand::random(), match_settings, sim.stage.arena.clone());
+    sim.inputs.clear();
+}
+
+pub fn update_menu_text(
+    sim: Res<Sim>,
+    menu: Res<MainMenu>,
+    settings: Res<Settings>,
+    controls_menu: Res<ControlsMenu>,
+    game_state_query: Query<&GameState>,
+    mut root_query: Query<&mut Style, With<MenuRoot>>,
+    mut text_query: Query<(&MenuText, &mut Text)>,
+) {
+    let shown = is_shown(game_state_query.iter(), &sim, &controls_menu);
+    let display = if shown { Display::Flex } else { Display::None };
+    for mut style in root_query.iter_mut() {
+        if style.display != display {
+            style.display = display;
+        }
+    }
+
+    let entries = menu.entries();
+    for (menu_text, mut text) in text_query.iter_mut() {
+        let (value, color) = match entries.get(menu_text.index) {
+            Some(entry) if shown => {
+                let color = if menu_text.index == menu.selected {
+                    Color::GOLD
+                } else {
+                    Color::WHITE
+                };
+                (menu.label(*entry, &settings), color)
+            }
+            _ => (String::new(), Color::WHITE),
+        };
+        let section = &text.sections[0];
+        if section.value != value || section.style.color != color {
+            let section = &mut text.sections[0];
+            section.value = value;
+            section.style.color = color;
+        }
+    }
+}
@@ -0,0 +1,519 @@
+//! Online versus over udp with rollback.
+//!
+//! Both players run the whole simulation. Every tick the local input is sent
+//! to the peer together with all inputs the peer has not acknowledged yet,
+//! so a lost packet is covered by the next one. Until the remote input for
+//! a tick arrives it is predicted to be the same as the last one received;
+//! when the real input turns out different the simulation is rolled back to
+//! the snapshot before that tick and simulated forward again. Events of the
+//! corrected ticks that the first timeline didn't have, e.g. a hit that was
+//! predicted to miss, are presented late rather than not at all.
+//!
+//! The host (`duel --host 7777`) picks the seed and the stage and plays
+//! player 1, the guest (`duel --join 192.168.0.2:7777`) plays player 2.
+//! `--net-latency` and `--net-loss` delay and drop outgoing packets, which
+//! is handy to try two instances on one machine.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::sim::{
+    read_bytes, Control, Difficulty, Fighter, MatchSettings, PlayerInput, Role, SimEvent,
+    Simulation,
+};
+use crate::stage::Stage;
+use crate::weapons;
+
+pub const SETTINGS: MatchSettings = MatchSettings {
+    opponent_role: Role::WeaponSpecialist,
+    opponent_control: Control::Human,
+    difficulty: Difficulty::Normal,
+};
+
+// local inputs take effect this many ticks late, which hides a good part of
+// the latency without any rollback
+const INPUT_DELAY: usize = 2;
+// how far the simulation may run ahead of the inputs received from the peer
+const MAX_PREDICTION: usize = 8;
+const MAX_PACKET_INPUTS: usize = 64;
+// large enough for the stage and weapon files in the start packet
+const MAX_PACKET_LEN: usize = 8192;
+
+const HELLO: u8 = b'H';
+const START: u8 = b'S';
+const INPUTS: u8 = b'I';
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetConditions {
+    pub latency: Duration,
+    /// chance to drop a packet, from 0 to 1
+    pub loss: f32,
+}
+
+/// the socket, with simulated latency and packet loss on the way out.
+struct Link {
+    socket: UdpSocket,
+    conditions: NetConditions,
+    queue: VecDeque<(Instant, SocketAddr, Vec<u8>)>,
+    rng: StdRng,
+}
+
+impl Link {
+    fn new(socket: UdpSocket, conditions: NetConditions) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        Ok(Link {
+            socket,
+            conditions,
+            queue: VecDeque::new(),
+            rng: StdRng::from_entropy(),
+        })
+    }
+
+    fn send(&mut self, addr: SocketAddr, packet: Vec<u8>) {
+        if self.rng.gen::<f32>() < self.conditions.loss {
+            return;
+        }
+        let due = Instant::now() + self.conditions.latency;
+        self.queue.push_back((due, addr, packet));
+        self.flush();
+    }
+
+    fn flush(&mut self) {
+        let now = Instant::now();
+        while self.queue.front().map_or(false, |(due, ..)| *due <= now) {
+            if let Some((_, addr, packet)) = self.queue.pop_front() {
+                // a full socket buffer is just another lost packet
+                let _ = self.socket.send_to(&packet, addr);
+            }
+        }
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> Option<(usize, SocketAddr)> {
+        self.socket.recv_from(buf).ok()
+    }
+}
+
+pub struct NetSession {
+    link: Link,
+    local: Fighter,
+    peer: Option<SocketAddr>,
+    seed: u64,
+    stage: Stage,
+    /// what the host answers a hello with
+    start_packet: Vec<u8>,
+    started: bool,
+    local_inputs: Vec<PlayerInput>,
+    /// the inputs received from the peer, without gaps
+    remote_inputs: Vec<PlayerInput>,
+    /// the remote input every simulated tick was stepped with
+    used_remote: Vec<PlayerInput>,
+    /// the state before every tick that is not confirmed yet
+    snapshots: VecDeque<Simulation>,
+    /// the events that were presented for every tick that is not confirmed
+    /// yet, lined up with `snapshots`
+    presented: VecDeque<Vec<SimEvent>>,
+    snapshot_base: usize,
+    rollback_from: Option<usize>,
+    /// how many local inputs the peer has received
+    acked: usize,
+}
+
+impl NetSession {
+    fn new(socket: UdpSocket, local: Fighter, conditions: NetConditions) -> io::Result<Self> {
+        Ok(NetSession {
+            link: Link::new(socket, conditions)?,
+            local,
+            peer: None,
+            seed: 0,
+            stage: Stage::default(),
+            start_packet: Vec::new(),
+            started: false,
+            local_inputs: Vec::new(),
+            remote_inputs: Vec::new(),
+            used_remote: Vec::new(),
+            snapshots: VecDeque::new(),
+            presented: VecDeque::new(),
+            snapshot_base: 0,
+            rollback_from: None,
+            acked: 0,
+        })
+    }
+
+    /// waits on `port` for a guest, the host plays player 1 on `stage`.
+    /// fails if the stage doesn't fit into the start packet.
+    pub fn host(port: u16, conditions: NetConditions, stage: Stage) -> io::Result<Self> {
+        let seed = rand::random();
+        let start_packet = start_packet(seed, &stage)?;
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        let mut session = NetSession::new(socket, Fighter::Player, conditions)?;
+        session.seed = seed;
+        session.stage = stage;
+        session.start_packet = start_packet;
+        Ok(session)
+    }
+
+    /// connects to the host at `addr`, the guest plays player 2.
+    pub fn join(addr: &str, conditions: NetConditions) -> io::Result<Self> {
+        let host = addr
+            .to_socket_addrs()?
+            .find(SocketAddr::is_ipv4)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no ipv4 address for host"))?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        let mut session = NetSession::new(socket, Fighter::Opponent, conditions)?;
+        session.peer = Some(host);
+        Ok(session)
+    }
+
+    pub fn local(&self) -> Fighter {
+        self.local
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// the stage of the match, the guest learns it when the match starts.
+    pub fn stage(&self) -> &Stage {
+        &self.stage
+    }
+
+    fn start(&mut self, seed: u64, sim: &mut Simulation) {
+        self.seed = seed;
+        self.started = true;
+        self.local_inputs = vec![PlayerInput::default(); INPUT_DELAY];
+        self.remote_inputs = vec![PlayerInput::default(); INPUT_DELAY];
+        self.used_remote.clear();
+        self.snapshots.clear();
+        self.presented.clear();
+        self.snapshot_base = 0;
+        self.rollback_from = None;
+        self.acked = INPUT_DELAY;
+        *sim = Simulation::with_arena(seed, SETTINGS, self.stage.arena.clone());
+    }
+
+    /// handles the packets that arrived and rolls back if a prediction was
+    /// wrong. should be called every frame, also once the match is over so
+    /// the peer gets the last inputs. returns true when the match started
+    /// and `sim` was reset for it. `sim.events` has the events of a rollback
+    /// that weren't presented yet.
+    pub fn poll(&mut self, sim: &mut Simulation) -> bool {
+        let was_started = self.started;
+        let mut buf = [0; MAX_PACKET_LEN];
+        while let Some((len, addr)) = self.link.recv(&mut buf) {
+            let packet = &buf[..len];
+            match packet.first() {
+                Some(&HELLO) if self.local == Fighter::Player => {
+                    if self.peer.is_none() {
+                        self.peer = Some(addr);
+                        self.start(self.seed, sim);
+                    }
+                    // answered every time, the first answer may get lost
+                    if self.peer == Some(addr) {
+                        self.link.send(addr, self.start_packet.clone());
+                    }
+                }
+                Some(&START) if !self.started && self.peer == Some(addr) => {
+                    if let Some((seed, stage)) = read_start(packet) {
+                        self.stage = stage;
+                        self.start(seed, sim);
+                    }
+                }
+                Some(&INPUTS) if self.started && self.peer == Some(addr) => {
+                    self.receive_inputs(packet);
+                }
+                _ => (),
+            }
+        }
+
+        match self.peer {
+            Some(host) if !self.started => self.link.send(host, vec![HELLO]),
+            _ => self.send_inputs(),
+        }
+        self.link.flush();
+        self.rollback(sim);
+        self.started && !was_started
+    }
+
+    /// steps one tick with the local input, unless the peer has fallen too
+    /// far behind. returns whether the tick was simulated, the events of the
+    /// new tick are in `sim.events`.
+    pub fn advance(&mut self, sim: &mut Simulation, input: PlayerInput) -> bool {
+        let tick = sim.tick as usize;
+        if !self.started || tick >= self.remote_inputs.len() + MAX_PREDICTION {
+            return false;
+        }
+        self.local_inputs.push(input);
+        self.send_inputs();
+        self.step(sim);
+        self.presented.push_back(sim.events.clone());
+        true
+    }
+
+    /// the winner as far as both players agree on it, a predicted tick might
+    /// still be rolled back.
+    pub fn confirmed_winner(&self, sim: &Simulation) -> Option<Fighter> {
+        let confirmed = self.remote_inputs.len();
+        if confirmed >= sim.tick as usize {
+            sim.winner()
+        } else {
+            self.snapshots
+                .get(confirmed - self.snapshot_base)
+                .and_then(Simulation::winner)
+        }
+    }
+
+    /// the inputs of both fighters for every confirmed tick, for the replay.
+    pub fn confirmed_inputs(&self) -> Vec<[PlayerInput; 2]> {
+        let confirmed = self.remote_inputs.len().min(self.local_inputs.len());
+        (0..confirmed)
+            .map(|tick| {
+                let mut inputs = [PlayerInput::default(); 2];
+                inputs[self.local.index()] = self.local_inputs[tick];
+                inputs[self.local.other().index()] = self.remote_inputs[tick];
+                inputs
+            })
+            .collect()
+    }
+
+    fn step(&mut self, sim: &mut Simulation) {
+        let tick = sim.tick as usize;
+        let remote = match self.remote_inputs.get(tick) {
+            Some(input) => *input,
+            None => self.remote_inputs.last().copied().unwrap_or_default(),
+        };
+        self.used_remote.truncate(tick);
+        self.used_remote.push(remote);
+        self.snapshots.truncate(tick - self.snapshot_base);
+        self.snapshots.push_back(sim.clone());
+
+        let mut inputs = [PlayerInput::default(); 2];
+        inputs[self.local.index()] = self.local_inputs[tick];
+        inputs[self.local.other().index()] = remote;
+        sim.step(&inputs);
+    }
+
+    fn rollback(&mut self, sim: &mut Simulation) {
+        let tick = sim.tick as usize;
+        let mut missed = Vec::new();
+        if let Some(from) = self.rollback_from.take() {
+            *sim = self.snapshots[from - self.snapshot_base].clone();
+            while (sim.tick as usize) < tick {
+                let at = sim.tick as usize - self.snapshot_base;
+                self.step(sim);
+                // most events were already played the first time around
+                let presented = &mut self.presented[at];
+                for event in &sim.events {
+                    if !presented.iter().any(|shown| same_event(shown, event)) {
+                        presented.push(*event);
+                        missed.push(*event);
+                    }
+                }
+            }
+        }
+        sim.events = missed;
+
+        // the snapshot after the last confirmed tick is kept for
+        // `confirmed_winner`
+        let confirmed = self.remote_inputs.len().min(tick);
+        while self.snapshot_base < confirmed {
+            self.snapshots.pop_front();
+            self.presented.pop_front();
+            self.snapshot_base += 1;
+        }
+    }
+
+    fn send_inputs(&mut self) {
+        let peer = match self.peer {
+            Some(peer) => peer,
+            None => return,
+        };
+        let first = self.acked.min(self.local_inputs.len());
+        let inputs = &self.local_inputs[first..];
+        let inputs = &inputs[..inputs.len().min(MAX_PACKET_INPUTS)];
+
+        let mut packet = Vec::with_capacity(10 + inputs.len() * PlayerInput::ENCODED_LEN);
+        packet.push(INPUTS);
+        packet.extend((first as u32).to_le_bytes());
+        packet.extend((self.remote_inputs.len() as u32).to_le_bytes());
+        packet.push(inputs.len() as u8);
+        for input in inputs {
+            input.encode(&mut packet);
+        }
+        self.link.send(peer, packet);
+    }
+
+    fn receive_inputs(&mut self, packet: &[u8]) {
+        let (first, ack, count) = match (
+            read_bytes(packet, 1).map(u32::from_le_bytes),
+            read_bytes(packet, 5).map(u32::from_le_bytes),
+            packet.get(9),
+        ) {
+            (Some(first), Some(ack), Some(count)) => {
+                (first as usize, ack as usize, *count as usize)
+            }
+            _ => return,
+        };
+        self.acked = self.acked.max(ack.min(self.local_inputs.len()));
+
+        for i in 0..count {
+            let tick = first + i;
+            let input = match PlayerInput::decode(
+                &packet[(10 + i * PlayerInput::ENCODED_LEN).min(packet.len())..],
+            ) {
+                Some(input) => input,
+                None => return,
+            };
+            if tick < self.remote_inputs.len() {
+                continue;
+            }
+            if tick > self.remote_inputs.len() {
+                // an old packet from before a gap, the next one covers it
+                return;
+            }
+            if matches!(self.used_remote.get(tick), Some(used) if *used != input) {
+                self.rollback_from = Some(self.rollback_from.map_or(tick, |from| from.min(tick)));
+            }
+            self.remote_inputs.push(input);
+        }
+    }
+}
+
+/// the same event in two timelines, maybe at another place.
+fn same_event(a: &SimEvent, b: &SimEvent) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b) && a.fighter() == b.fighter()
+}
+
+/// the seed, the stage id, then the stage file and the weapon files, each
+/// after its length. a stage that would be cut off is refused, the guest
+/// would play somewhere else.
+fn start_packet(seed: u64, stage: &Stage) -> io::Result<Vec<u8>> {
+    if stage.id.len() > u8::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the stage id is longer than {} bytes", u8::MAX),
+        ));
+    }
+    let mut start = vec![START];
+    start.extend(seed.to_le_bytes());
+    start.push(stage.id.len() as u8);
+    start.extend(stage.id.as_bytes());
+    let [gun, shotgun] = weapons::all_to_text(&stage.arena.projectiles);
+    for text in [stage.to_text(), gun, shotgun] {
+        start.extend((text.len() as u32).to_le_bytes());
+        start.extend(text.as_bytes());
+    }
+    if start.len() > MAX_PACKET_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the stage {} is too large to send, {} bytes of at most {}",
+                stage.id,
+                start.len(),
+                MAX_PACKET_LEN
+            ),
+        ));
+    }
+    Ok(start)
+}
+
+/// the seed and the stage sent by the host.
+fn read_start(packet: &[u8]) -> Option<(u64, Stage)> {
+    let seed = u64::from_le_bytes(read_bytes(packet, 1)?);
+    let id_len = *packet.get(9)? as usize;
+    let id = std::str::from_utf8(packet.get(10..10 + id_len)?).ok()?;
+    let mut at = 10 + id_len;
+    let mut text = || {
+        let len = u32::from_le_bytes(read_bytes(packet, at)?) as usize;
+        let text = std::str::from_utf8(packet.get(at + 4..at + 4 + len)?).ok()?;
+        at += 4 + len;
+        Some(text)
+    };
+    let stage_text = text()?;
+    let projectiles = weapons::all_from_text([text()?, text()?]);
+    Some((seed, Stage::from_text(id, stage_text, projectiles)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local_input(tick: usize) -> PlayerInput {
+        PlayerInput {
+            right: tick % 80 < 30,
+            attack: tick % 35 < 2,
+            ..Default::default()
+        }
+    }
+
+    fn remote_input(tick: usize) -> PlayerInput {
+        PlayerInput {
+            left: tick % 60 < 25,
+            jump: tick % 75 == 0,
+            attack: tick % 45 < 2,
+            ..Default::default()
+        }
+    }
+
+    /// an inputs packet from the peer with `count` of its inputs from `first`.
+    fn inputs_packet(first: usize, count: usize) -> Vec<u8> {
+        let mut packet = vec![INPUTS];
+        packet.extend((first as u32).to_le_bytes());
+        packet.extend(0u32.to_le_bytes());
+        packet.push(count as u8);
+        for tick in first..first + count {
+            remote_input(tick).encode(&mut packet);
+        }
+        packet
+    }
+
+    #[test]
+    fn the_start_packet_carries_the_stage() {
+        let stage = Stage::default();
+        let packet = start_packet(42, &stage).unwrap();
+        let (seed, read) = read_start(&packet).expect("the packet reads back");
+        assert_eq!(seed, 42);
+        assert_eq!(read.id, stage.id);
+        assert_eq!(read.to_text(), stage.to_text());
+        assert_eq!(read.arena.projectiles, stage.arena.projectiles);
+    }
+
+    #[test]
+    fn rollbacks_end_up_where_the_real_inputs_lead() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut session = NetSession::new(socket, Fighter::Player, NetConditions::default())
+            .expect("a local socket");
+        let mut sim = Simulation::new(0, SETTINGS);
+        session.start(5, &mut sim);
+
+        // the peer's inputs arrive in bursts, after the ticks were predicted
+        while sim.tick < 600 {
+            while session.advance(&mut sim, local_input(session.local_inputs.len())) {}
+            let first = session.remote_inputs.len();
+            session.receive_inputs(&inputs_packet(first, MAX_PREDICTION));
+            session.poll(&mut sim);
+        }
+        let tick = sim.tick as usize;
+        let first = session.remote_inputs.len();
+        session.receive_inputs(&inputs_packet(first, tick.saturating_sub(first)));
+        session.poll(&mut sim);
+
+        let mut expected = Simulation::with_arena(5, SETTINGS, session.stage.arena.clone());
+        for inputs in session.confirmed_inputs() {
+            expected.step(&inputs);
+        }
+        assert_eq!(expected.tick, sim.tick);
+        assert_eq!(
+            format!("{:?}", expected.fighters),
+            format!("{:?}", sim.fighters)
+        );
+        assert_eq!(
+            format!("{:?}", expected.bullets),
+            format!("{:?}", sim.bullets)
+        );
+    }
+}
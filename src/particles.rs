@@ -0,0 +1,251 @@
+//! Particle effects.
+//!
+//! Every particle is a small square sprite moved on the cpu: it flies off
+//! with a velocity, falls with gravity, slows down with drag and fades from
+//! one colour to another while it shrinks, until its lifetime is over. An
+//! `Emitter` describes a burst of them and the events of the simulation set
+//! them off: bullet and glove impacts, sparks where a bullet strikes a wall
+//! or a guard, the muzzle flash of a shot, dust when a fighter lands hard and
+//! an explosion for a knockout. The effects stop while the game is paused and
+//! run on the time of the fight.
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use std::f32::consts::{PI, TAU};
+
+use crate::game_time::GameTime;
+use crate::sim::{HitCause, SimEvent};
+use crate::GameState;
+
+// more than this many particles at once and new bursts are skipped
+const MAX_PARTICLES: usize = 600;
+// how far the muzzle is from the middle of a weapon, half its sprite
+const MUZZLE_DISTANCE: f32 = 32.0;
+// a landing this fast kicks up the most dust
+const HARD_LANDING_SPEED: f32 = 5000.0;
+const PARTICLE_Z: f32 = 5.0;
+
+/// a burst of particles, the pairs are ranges picked from at random.
+struct Emitter {
+    count: usize,
+    /// in world units per second
+    speed: (f32, f32),
+    /// radians to either side of the direction the burst goes in
+    spread: f32,
+    /// in seconds
+    lifetime: (f32, f32),
+    size: (f32, f32),
+    start_color: Color,
+    end_color: Color,
+    /// world units per second squared, pulling down
+    gravity: f32,
+    /// the share of the speed lost per second
+    drag: f32,
+}
+
+const BULLET_IMPACT: Emitter = Emitter {
+    count: 12,
+    speed: (150.0, 400.0),
+    spread: 0.8,
+    lifetime: (0.15, 0.35),
+    size: (3.0, 6.0),
+    start_color: Color::rgb(1.0, 0.9, 0.4),
+    end_color: Color::rgba(0.8, 0.2, 0.0, 0.0),
+    gravity: 600.0,
+    drag: 2.0,
+};
+
+const GLOVE_IMPACT: Emitter = Emitter {
+    count: 16,
+    speed: (100.0, 300.0),
+    spread: PI,
+    lifetime: (0.2, 0.4),
+    size: (5.0, 10.0),
+    start_color: Color::rgb(1.0, 1.0, 1.0),
+    end_color: Color::rgba(1.0, 0.3, 0.3, 0.0),
+    gravity: 0.0,
+    drag: 4.0,
+};
+
+const HAZARD_IMPACT: Emitter = Emitter {
+    count: 10,
+    speed: (80.0, 200.0),
+    spread: 0.6,
+    lifetime: (0.3, 0.6),
+    size: (4.0, 8.0),
+    start_color: Color::rgb(1.0, 0.5, 0.1),
+    end_color: Color::rgba(0.3, 0.3, 0.3, 0.0),
+    gravity: -150.0,
+    drag: 1.0,
+};
+
+const MUZZLE_FLASH: Emitter = Emitter {
+    count: 8,
+    speed: (200.0, 500.0),
+    spread: 0.3,
+    lifetime: (0.05, 0.12),
+    size: (6.0, 12.0),
+    start_color: Color::rgb(1.0, 1.0, 0.7),
+    end_color: Color::rgba(1.0, 0.5, 0.0, 0.0),
+    gravity: 0.0,
+    drag: 6.0,
+};
+
+// sent up, spreading out to both sides along the ground
+const LANDING_DUST: Emitter = Emitter {
+    count: 10,
+    speed: (40.0, 160.0),
+    spread: 1.45,
+    lifetime: (0.3, 0.6),
+    size: (6.0, 12.0),
+    start_color: Color::rgba(0.85, 0.8, 0.7, 0.8),
+    end_color: Color::rgba(0.85, 0.8, 0.7, 0.0),
+    gravity: -40.0,
+    drag: 3.0,
+};
+
+const KNOCK_OUT: Emitter = Emitter {
+    count: 60,
+    speed: (150.0, 700.0),
+    spread: PI,
+    lifetime: (0.5, 1.2),
+    size: (6.0, 16.0),
+    start_color: Color::rgb(1.0, 0.95, 0.6),
+    end_color: Color::rgba(0.6, 0.1, 0.0, 0.0),
+    gravity: 300.0,
+    drag: 1.5,
+};
+
+#[derive(Component)]
+pub struct Particle {
+    vel: Vec2,
+    /// seconds since it was emitted
+    age: f32,
+    lifetime: f32,
+    size: f32,
+    start_color: Color,
+    end_color: Color,
+    gravity: f32,
+    drag: f32,
+}
+
+impl Emitter {
+    /// sends the burst from `pos` towards `dir`, with `amount` times the
+    /// particles.
+    fn emit(&self, commands: &mut Commands, pos: Vec2, dir: Vec2, amount: f32) {
+        let mut rng = rand::thread_rng();
+        let base = dir.y.atan2(dir.x);
+        let count = (self.count as f32 * amount).round() as usize;
+        for _ in 0..count {
+            let angle = base + rng.gen_range(-self.spread..=self.spread);
+            let speed = rng.gen_range(self.speed.0..=self.speed.1);
+            let size = rng.gen_range(self.size.0..=self.size.1);
+            let particle = Particle {
+                vel: Vec2::new(angle.cos(), angle.sin()) * speed,
+                age: 0.0,
+                lifetime: rng.gen_range(self.lifetime.0..=self.lifetime.1),
+                size,
+                start_color: self.start_color,
+                end_color: self.end_color,
+                gravity: self.gravity,
+                drag: self.drag,
+            };
+            commands
+                .spawn_bundle(SpriteBundle {
+                    transform: Transform {
+                        translation: pos.extend(PARTICLE_Z),
+                        rotation: Quat::from_rotation_z(rng.gen_range(0.0..TAU)),
+                        ..Default::default()
+                    },
+                    sprite: Sprite {
+                        color: self.start_color,
+                        custom_size: Some(Vec2::splat(size)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(particle);
+        }
+    }
+}
+
+/// sets off the effects of what happened in the simulation.
+pub fn spawn_particles(
+    mut commands: Commands,
+    mut events: EventReader<SimEvent>,
+    particle_query: Query<(), With<Particle>>,
+) {
+    let mut room = MAX_PARTICLES.saturating_sub(particle_query.iter().count());
+    for event in events.iter() {
+        let (emitter, pos, dir, amount) = match *event {
+            SimEvent::Shot { pos, dir, .. } => {
+                (&MUZZLE_FLASH, pos + dir * MUZZLE_DISTANCE, dir, 1.0)
+            }
+            SimEvent::Hit {
+                pos,
+                cause: HitCause::Bullet,
+                ..
+            } => (&BULLET_IMPACT, pos, Vec2::Y, 1.0),
+            SimEvent::Hit {
+                pos,
+                cause: HitCause::Punch,
+                ..
+            } => (&GLOVE_IMPACT, pos, Vec2::X, 1.0),
+            SimEvent::Hit {
+                pos,
+                cause: HitCause::Hazard,
+                ..
+            } => (&HAZARD_IMPACT, pos, Vec2::Y, 1.0),
+            SimEvent::Land { pos, speed, .. } => {
+                let amount = (speed / HARD_LANDING_SPEED).min(1.0);
+                (&LANDING_DUST, pos, Vec2::Y, amount)
+            }
+            SimEvent::KnockOut { pos, .. } => (&KNOCK_OUT, pos, Vec2::Y, 1.0),
+            SimEvent::Impact { pos, normal, .. } => (&BULLET_IMPACT, pos, normal, 0.5),
+            SimEvent::Punch { .. } => continue,
+        };
+        if room < emitter.count {
+            continue;
+        }
+        room -= emitter.count;
+        emitter.emit(&mut commands, pos, dir, amount);
+    }
+}
+
+/// moves, fades and shrinks the particles and removes them once they are
+/// over.
+pub fn update_particles(
+    mut commands: Commands,
+    game_time: Res<GameTime>,
+    game_state_query: Query<&GameState>,
+    mut particle_query: Query<(Entity, &mut Particle, &mut Transform, &mut Sprite)>,
+) {
+    if game_state_query
+        .iter()
+        .any(|game_state| game_state.state == 2)
+    {
+        return;
+    }
+    let dt = game_time.delta_seconds();
+    for (entity, mut particle, mut transform, mut sprite) in particle_query.iter_mut() {
+        particle.age += dt;
+        if particle.age >= particle.lifetime {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let t = particle.age / particle.lifetime;
+        particle.vel.y -= particle.gravity * dt;
+        particle.vel *= (1.0 - particle.drag * dt).max(0.0);
+        transform.translation += (particle.vel * dt).extend(0.0);
+
+        let (start, end) = (particle.start_color, particle.end_color);
+        sprite.color = Color::rgba(
+            start.r() + (end.r() - start.r()) * t,
+            start.g() + (end.g() - start.g()) * t,
+            start.b() + (end.b() - start.b()) * t,
+            start.a() + (end.a() - start.a()) * t,
+        );
+        sprite.custom_size = Some(Vec2::splat(particle.size * (1.0 - 0.5 * t)));
+    }
+}
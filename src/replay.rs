@@ -0,0 +1,450 @@
+//! Match replays.
+//!
+//! The simulation is deterministic, so the seed, the settings, the stage and
+//! the inputs of every tick are all it takes to play a match again. The
+//! whole stage file and the weapon files are stored, so editing them later
+//! doesn't change old replays. Finished matches are written to `replays/`, `R` on the title
+//! screen watches the latest one and `duel --replay <file>` a specific one.
+//!
+//! The viewer steps the same `Sim` the game does, so everything on screen
+//! comes from the usual systems. Space pauses, `.` steps a single tick while
+//! paused, up and down change the speed, left and right jump five seconds,
+//! home goes back to the start and escape returns to the title screen.
+
+use bevy::prelude::*;
+
+use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use crate::controls_menu::ControlsMenu;
+use crate::game_time::GameTime;
+use crate::sim::{
+    self, read_bytes, Control, Difficulty, MatchSettings, PlayerInput, Role, SimEvent, Simulation,
+};
+use crate::stage::Stage;
+use crate::weapons;
+use crate::{GameState, Sim};
+
+#[cfg(not(target_arch = "wasm32"))]
+const REPLAY_DIR: &str = "replays";
+const MAGIC: &[u8; 8] = b"DUELRPL4";
+
+// scrubbing restores the closest earlier keyframe and simulates from there
+const KEYFRAME_TICKS: usize = sim::TICK_RATE as usize;
+const SEEK_TICKS: usize = 5 * sim::TICK_RATE as usize;
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 4.0;
+// enough to keep up with the highest speed at a low frame rate
+const MAX_TICKS_PER_FRAME: u32 = 20;
+
+#[derive(Clone, Debug)]
+pub struct Replay {
+    pub seed: u64,
+    pub settings: MatchSettings,
+    pub stage: Stage,
+    /// the inputs of both fighters for every tick
+    pub inputs: Vec<[PlayerInput; 2]>,
+}
+
+impl Replay {
+    /// the header, the stage file and the weapon files followed by runs of
+    /// equal inputs, each a `u16` repeat count and the two inputs.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(self.seed.to_le_bytes());
+        bytes.push(match self.settings.opponent_role {
+            Role::Boxer => 0,
+            Role::WeaponSpecialist => 1,
+        });
+        bytes.push(match self.settings.opponent_control {
+            Control::Human => 0,
+            Control::Ai => 1,
+            Control::Dummy => 2,
+        });
+        bytes.push(match self.settings.difficulty {
+            Difficulty::Easy => 0,
+            Difficulty::Normal => 1,
+            Difficulty::Hard => 2,
+        });
+        let [gun, shotgun] = weapons::all_to_text(&self.stage.arena.projectiles);
+        for text in [self.stage.id.clone(), self.stage.to_text(), gun, shotgun] {
+            bytes.extend((text.len() as u32).to_le_bytes());
+            bytes.extend(text.as_bytes());
+        }
+        bytes.extend((self.inputs.len() as u32).to_le_bytes());
+
+        let mut ticks = self.inputs.iter().peekable();
+        while let Some(inputs) = ticks.next() {
+            let mut repeat: u16 = 1;
+            while repeat < u16::MAX && ticks.peek() == Some(&inputs) {
+                ticks.next();
+                repeat += 1;
+            }
+            bytes.extend(repeat.to_le_bytes());
+            inputs[0].encode(&mut bytes);
+            inputs[1].encode(&mut bytes);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Replay> {
+        if !bytes.starts_with(MAGIC) {
+            return None;
+        }
+        let seed = u64::from_le_bytes(read_bytes(bytes, 8)?);
+        let opponent_role = match bytes.get(16)? {
+            0 => Role::Boxer,
+            1 => Role::WeaponSpecialist,
+            _ => return None,
+        };
+        let opponent_control = match bytes.get(17)? {
+            0 => Control::Human,
+            1 => Control::Ai,
+            2 => Control::Dummy,
+            _ => return None,
+        };
+        let difficulty = match bytes.get(18)? {
+            0 => Difficulty::Easy,
+            1 => Difficulty::Normal,
+            2 => Difficulty::Hard,
+            _ => return None,
+        };
+        let mut at = 19;
+        let mut text = || {
+            let len = u32::from_le_bytes(read_bytes(bytes, at)?) as usize;
+            let text = std::str::from_utf8(bytes.get(at + 4..at + 4 + len)?).ok()?;
+            at += 4 + len;
+            Some(text)
+        };
+        let stage_id = text()?;
+        let stage_text = text()?;
+        let projectiles = weapons::all_from_text([text()?, text()?]);
+        let stage = Stage::from_text(stage_id, stage_text, projectiles);
+        let ticks = u32::from_le_bytes(read_bytes(bytes, at)?) as usize;
+
+        let mut inputs = Vec::new();
+        at += 4;
+        while inputs.len() < ticks {
+            let repeat = u16::from_le_bytes(read_bytes(bytes, at)?) as usize;
+            let player = PlayerInput::decode(bytes.get(at + 2..)?)?;
+            let opponent = PlayerInput::decode(bytes.get(at + 2 + PlayerInput::ENCODED_LEN..)?)?;
+            inputs.extend(std::iter::repeat([player, opponent]).take(repeat));
+            at += 2 + 2 * PlayerInput::ENCODED_LEN;
+        }
+        inputs.truncate(ticks);
+
+        Some(Replay {
+            seed,
+            settings: MatchSettings {
+                opponent_role,
+                opponent_control,
+                difficulty,
+            },
+            stage,
+            inputs,
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        Replay::from_bytes(&std::fs::read(path)?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a replay"))
+    }
+
+    /// the most recently written replay.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_latest() -> io::Result<Replay> {
+        let mut latest = None;
+        for entry in std::fs::read_dir(REPLAY_DIR)? {
+            let entry = entry?;
+            let modified = entry.metadata()?.modified()?;
+            if latest.as_ref().map_or(true, |(time, _)| modified > *time) {
+                latest = Some((modified, entry.path()));
+            }
+        }
+        match latest {
+            Some((_, path)) => Replay::load(&path),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no replays yet")),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load_latest() -> io::Result<Replay> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "replays are not stored in the browser",
+        ))
+    }
+
+    /// writes the replay to `replays/match-<unix time>.replay`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) -> io::Result<()> {
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        std::fs::create_dir_all(REPLAY_DIR)?;
+        let path = Path::new(REPLAY_DIR).join(format!("match-{}.replay", time));
+        std::fs::write(path, self.to_bytes())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// the state before the first tick.
+    pub fn simulation(&self) -> Simulation {
+        Simulation::with_arena(self.seed, self.settings, self.stage.arena.clone())
+    }
+}
+
+pub struct ReplayViewer {
+    replay: Option<Replay>,
+    /// the state at every `KEYFRAME_TICKS`th tick
+    keyframes: Vec<Simulation>,
+    paused: bool,
+    speed: f32,
+    accumulator: f32,
+}
+
+impl Default for ReplayViewer {
+    fn default() -> Self {
+        ReplayViewer {
+            replay: None,
+            keyframes: Vec::new(),
+            paused: false,
+            speed: 1.0,
+            accumulator: 0.0,
+        }
+    }
+}
+
+impl ReplayViewer {
+    /// starts watching `replay` the next frame.
+    pub fn open(&mut self, replay: Replay) {
+        let mut sim = replay.simulation();
+        self.keyframes.clear();
+        for tick in 0..=replay.inputs.len() {
+            if tick % KEYFRAME_TICKS == 0 {
+                self.keyframes.push(sim.clone());
+            }
+            if let Some(inputs) = replay.inputs.get(tick) {
+                sim.step(inputs);
+            }
+        }
+        *self = ReplayViewer {
+            replay: Some(replay),
+            keyframes: std::mem::take(&mut self.keyframes),
+            ..Default::default()
+        };
+    }
+
+    fn len(&self) -> usize {
+        self.replay.as_ref().map_or(0, |replay| replay.inputs.len())
+    }
+
+    fn seek(&self, tick: usize, sim: &mut Simulation) {
+        let replay = match &self.replay {
+            Some(replay) => replay,
+            None => return,
+        };
+        let tick = tick.min(replay.inputs.len());
+        *sim = self.keyframes[tick / KEYFRAME_TICKS].clone();
+        while (sim.tick as usize) < tick {
+            sim.step(&replay.inputs[sim.tick as usize]);
+        }
+        sim.events.clear();
+    }
+
+    /// steps one tick, false at the end of the replay.
+    fn step(&self, sim: &mut Simulation) -> bool {
+        match self
+            .replay
+            .as_ref()
+            .and_then(|replay| replay.inputs.get(sim.tick as usize))
+        {
+            Some(inputs) => {
+                sim.step(inputs);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ReplayText;
+
+pub fn setup_replay_viewer(mut commands: Commands) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(80.0),
+                    left: Val::Px(20.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(ReplayText);
+}
+
+pub fn update_replay_viewer(
+    game_time: Res<GameTime>,
+    keyboard_input: Res<Input<KeyCode>>,
+    menu: Res<ControlsMenu>,
+    asset_server: Res<AssetServer>,
+    mut viewer: ResMut<ReplayViewer>,
+    mut sim: ResMut<Sim>,
+    mut sim_events: EventWriter<SimEvent>,
+    mut game_state_query: Query<&mut GameState>,
+    mut text_query: Query<&mut Text, With<ReplayText>>,
+) {
+    if sim.online {
+        return;
+    }
+    for mut game_state in game_state_query.iter_mut() {
+        if game_state.state == 0 && !menu.open && keyboard_input.just_pressed(KeyCode::R) {
+            match Replay::load_latest() {
+                Ok(replay) => viewer.open(replay),
+                Err(err) => warn!("could not load a replay: {}", err),
+            }
+        }
+        if game_state.state != 3 {
+            if viewer.replay.is_none() {
+                continue;
+            }
+            game_state.state = 3;
+            if let Some(replay) = &viewer.replay {
+                sim.stage = replay.stage.clone();
+            }
+            viewer.seek(0, &mut sim.simulation);
+        }
+
+        let tick = sim.simulation.tick as usize;
+        if keyboard_input.just_pressed(KeyCode::Escape) {
+            *viewer = ReplayViewer::default();
+            game_state.state = 0;
+            let arena = sim.stage.arena.clone();
+            sim.simulation =
+                Simulation::with_arena(rand::random(), MatchSettings::default(), arena);
+            for mut text in text_query.iter_mut() {
+                text.sections = Vec::new();
+            }
+            continue;
+        } else if keyboard_input.just_pressed(KeyCode::Space) {
+            viewer.paused = !viewer.paused;
+        } else if keyboard_input.just_pressed(KeyCode::Period) && viewer.paused {
+            viewer.step(&mut sim.simulation);
+            sim_events.send_batch(sim.simulation.events.iter().copied());
+        } else if keyboard_input.just_pressed(KeyCode::Up) {
+            viewer.speed = (viewer.speed * 2.0).min(MAX_SPEED);
+        } else if keyboard_input.just_pressed(KeyCode::Down) {
+            viewer.speed = (viewer.speed / 2.0).max(MIN_SPEED);
+        } else if keyboard_input.just_pressed(KeyCode::Left) {
+            viewer.seek(tick.saturating_sub(SEEK_TICKS), &mut sim.simulation);
+        } else if keyboard_input.just_pressed(KeyCode::Right) {
+            viewer.seek(tick + SEEK_TICKS, &mut sim.simulation);
+        } else if keyboard_input.just_pressed(KeyCode::Home) {
+            viewer.seek(0, &mut sim.simulation);
+        }
+
+        if viewer.paused {
+            viewer.accumulator = 0.0;
+        } else {
+            viewer.accumulator += game_time.delta_seconds() * viewer.speed;
+            let mut ticks = 0;
+            while viewer.accumulator >= sim::DT && ticks < MAX_TICKS_PER_FRAME {
+                viewer.accumulator -= sim::DT;
+                ticks += 1;
+                if !viewer.step(&mut sim.simulation) {
+                    viewer.accumulator = 0.0;
+                    break;
+                }
+                sim_events.send_batch(sim.simulation.events.iter().copied());
+            }
+            if ticks == MAX_TICKS_PER_FRAME {
+                viewer.accumulator = 0.0;
+            }
+        }
+
+        let font = asset_server.load("fonts/IceCaps.ttf");
+        let line = |value: String| TextSection {
+            value,
+            style: TextStyle {
+                font_size: 30.0,
+                font: font.clone(),
+                color: Color::WHITE,
+            },
+        };
+        let status = format!(
+            "Replay {} / {}  x{}{}\n",
+            clock(sim.simulation.tick as usize),
+            clock(viewer.len()),
+            viewer.speed,
+            if viewer.paused { "  paused" } else { "" }
+        );
+        for mut text in text_query.iter_mut() {
+            text.sections = vec![
+                line(status.clone()),
+                line(
+                    "space pause, . step, left/right seek, up/down speed, escape exit".to_string(),
+                ),
+            ];
+        }
+    }
+}
+
+/// minutes and seconds of game time.
+fn clock(ticks: usize) -> String {
+    let seconds = ticks / sim::TICK_RATE as usize;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_saved_replay_plays_to_the_same_winner() {
+        let settings = MatchSettings {
+            opponent_role: Role::Boxer,
+            opponent_control: Control::Ai,
+            difficulty: Difficulty::Hard,
+        };
+        let stage = Stage::default();
+        let mut sim = Simulation::with_arena(11, settings, stage.arena.clone());
+        let mut inputs = Vec::new();
+        while sim.winner().is_none() && inputs.len() < 60 * sim::TICK_RATE as usize {
+            let tick = inputs.len();
+            let player = PlayerInput {
+                right: tick % 100 < 40,
+                attack: tick % 30 < 2,
+                aim: Vec2::new(1.0, 0.0),
+                ..Default::default()
+            }
+            .without_idle_aim();
+            inputs.push([player, PlayerInput::default()]);
+            sim.step(&[player, PlayerInput::default()]);
+        }
+        assert!(sim.winner().is_some());
+
+        let replay = Replay {
+            seed: 11,
+            settings,
+            stage,
+            inputs,
+        };
+        let replay = Replay::from_bytes(&replay.to_bytes()).expect("the replay reads back");
+        let mut replayed = replay.simulation();
+        for inputs in &replay.inputs {
+            replayed.step(inputs);
+        }
+        assert_eq!(replayed.tick, sim.tick);
+        assert_eq!(replayed.winner(), sim.winner());
+    }
+}
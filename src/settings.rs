@@ -0,0 +1,323 @@
+//! What the player picked in the settings menu, kept between runs.
+//!
+//! The settings are saved to `duel/settings.cfg` in the config directory of
+//! the platform, e.g. `~/.config/duel/settings.cfg` on linux, and to the
+//! `localStorage` of the page in the browser. The file has one
+//! `key = value` line per setting followed by the controls, in the format of
+//! `src/input_map.rs`:
+//!
+//! ```text
+//! window = windowed
+//! window_size = 1280 720
+//! master_volume = 1.00
+//! effects_volume = 1.00
+//! music_volume = 0.70
+//! difficulty = normal
+//! stage = arena
+//! player1.jump = W, Pad South
+//! ```
+//!
+//! Changes are written shortly after they are made.
+
+use bevy::prelude::*;
+use bevy::window::{WindowMode, WindowResized};
+
+use std::io;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+
+use crate::input_map::InputMap;
+use crate::sim::Difficulty;
+use crate::stage;
+
+#[cfg(not(target_arch = "wasm32"))]
+const SETTINGS_FILE: &str = "settings.cfg";
+#[cfg(target_arch = "wasm32")]
+const STORAGE_KEY: &str = "duel.settings";
+// seconds without changes before the settings are written, so resizing the
+// window doesn't write on every frame
+const SAVE_DELAY: f64 = 0.5;
+
+// how much a volume changes with one press
+pub const VOLUME_STEP: f32 = 0.1;
+
+pub const WINDOW_MODES: [WindowMode; 3] = [
+    WindowMode::Windowed,
+    WindowMode::BorderlessFullscreen,
+    WindowMode::Fullscreen,
+];
+
+/// the lines of the settings file that were ignored when it was loaded.
+#[derive(Clone, Debug, Default)]
+pub struct InvalidLines(pub Vec<String>);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// from 0 to 1, scales the effects and the music
+    pub master_volume: f32,
+    pub effects_volume: f32,
+    pub music_volume: f32,
+    pub window_mode: WindowMode,
+    /// in logical pixels, of the window when it isn't fullscreen
+    pub window_size: Vec2,
+    /// of the ai in matches against it
+    pub difficulty: Difficulty,
+    /// the id of the stage picked last
+    pub stage: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            master_volume: 1.0,
+            effects_volume: 1.0,
+            music_volume: 0.7,
+            window_mode: WindowMode::Windowed,
+            window_size: Vec2::new(1280.0, 720.0),
+            difficulty: Difficulty::Normal,
+            stage: stage::DEFAULT_STAGE.to_string(),
+        }
+    }
+}
+
+impl Settings {
+    /// the volume sound effects play at.
+    pub fn effects(&self) -> f32 {
+        self.master_volume * self.effects_volume
+    }
+
+    /// the volume the music plays at.
+    pub fn music(&self) -> f32 {
+        self.master_volume * self.music_volume
+    }
+
+    /// reads the settings file on top of the defaults, together with the
+    /// controls in it and the lines that were ignored.
+    pub fn from_config(text: &str) -> (Settings, InputMap, InvalidLines) {
+        let mut settings = Settings::default();
+        let mut input_map = InputMap::default();
+        let mut invalid = InvalidLines::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = match line.split_once('=') {
+                Some((key, _)) if key.contains('.') => input_map.parse_line(line),
+                Some((key, value)) => settings.parse(key.trim(), value.trim()).is_some(),
+                None => false,
+            };
+            if !parsed {
+                invalid.0.push(line.to_string());
+            }
+        }
+        (settings, input_map, invalid)
+    }
+
+    fn parse(&mut self, key: &str, value: &str) -> Option<()> {
+        match key {
+            "window" => {
+                self.window_mode = match value {
+                    "windowed" => WindowMode::Windowed,
+                    "borderless" => WindowMode::BorderlessFullscreen,
+                    "fullscreen" => WindowMode::Fullscreen,
+                    _ => return None,
+                }
+            }
+            "window_size" => {
+                let (width, height) = value.split_once(' ')?;
+                let size = Vec2::new(width.trim().parse().ok()?, height.trim().parse().ok()?);
+                if size.min_element() <= 0.0 {
+                    return None;
+                }
+                self.window_size = size;
+            }
+            "master_volume" => self.master_volume = parse_volume(value)?,
+            "effects_volume" => self.effects_volume = parse_volume(value)?,
+            "music_volume" => self.music_volume = parse_volume(value)?,
+            "difficulty" => {
+                self.difficulty = *Difficulty::ALL
+                    .iter()
+                    .find(|difficulty| difficulty.label().eq_ignore_ascii_case(value))?
+            }
+            "stage" if !value.is_empty() => self.stage = value.to_string(),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    pub fn to_config(&self, input_map: &InputMap) -> String {
+        let window = match self.window_mode {
+            WindowMode::Windowed => "windowed",
+            WindowMode::BorderlessFullscreen => "borderless",
+            WindowMode::SizedFullscreen | WindowMode::Fullscreen => "fullscreen",
+        };
+        let mut text = String::from("# duel settings, see src/settings.rs\n");
+        text.push_str(&format!("window = {}\n", window));
+        text.push_str(&format!(
+            "window_size = {} {}\n",
+            self.window_size.x, self.window_size.y
+        ));
+        text.push_str(&format!("master_volume = {:.2}\n", self.master_volume));
+        text.push_str(&format!("effects_volume = {:.2}\n", self.effects_volume));
+        text.push_str(&format!("music_volume = {:.2}\n", self.music_volume));
+        text.push_str(&format!(
+            "difficulty = {}\n",
+            self.difficulty.label().to_lowercase()
+        ));
+        text.push_str(&format!("stage = {}\n", self.stage));
+        text.push_str(&input_map.to_config());
+        text
+    }
+
+    /// the saved settings and controls, or the defaults if there are none.
+    /// it runs before the app, so the ignored lines are logged later by
+    /// `warn_invalid_lines`.
+    pub fn load() -> (Settings, InputMap, InvalidLines) {
+        match read_config() {
+            Some(text) => Settings::from_config(&text),
+            None => Default::default(),
+        }
+    }
+
+    pub fn save(&self, input_map: &InputMap) -> io::Result<()> {
+        write_config(&self.to_config(input_map))
+    }
+}
+
+fn parse_volume(value: &str) -> Option<f32> {
+    let volume: f32 = value.parse().ok()?;
+    (0.0..=1.0).contains(&volume).then(|| volume)
+}
+
+/// `duel/settings.cfg` in the platform's config directory.
+#[cfg(not(target_arch = "wasm32"))]
+fn config_path() -> Option<PathBuf> {
+    let var = |name: &str| std::env::var_os(name).map(PathBuf::from);
+    let dir = if cfg!(windows) {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        var("XDG_CONFIG_HOME")
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| var("HOME").map(|home| home.join(".config")))
+    };
+    dir.map(|dir| dir.join("duel").join(SETTINGS_FILE))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_config() -> Option<String> {
+    std::fs::read_to_string(config_path()?).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_config(text: &str) -> io::Result<()> {
+    let path = config_path()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, text)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_config() -> Option<String> {
+    local_storage()?.get_item(STORAGE_KEY).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_config(text: &str) -> io::Result<()> {
+    local_storage()
+        .and_then(|storage| storage.set_item(STORAGE_KEY, text).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "localStorage is not available"))
+}
+
+/// the window to open with `settings`.
+pub fn window_descriptor(settings: &Settings) -> WindowDescriptor {
+    WindowDescriptor {
+        title: "duel".to_string(),
+        width: settings.window_size.x,
+        height: settings.window_size.y,
+        mode: settings.window_mode,
+        ..Default::default()
+    }
+}
+
+pub fn window_mode_label(mode: WindowMode) -> &'static str {
+    match mode {
+        WindowMode::Windowed => "Windowed",
+        WindowMode::BorderlessFullscreen => "Borderless",
+        WindowMode::SizedFullscreen | WindowMode::Fullscreen => "Fullscreen",
+    }
+}
+
+/// `volume` moved by `steps` presses, kept between 0 and 1.
+pub fn step_volume(volume: f32, steps: i32) -> f32 {
+    (volume + steps as f32 * VOLUME_STEP).clamp(0.0, 1.0)
+}
+
+pub fn apply_window_mode(settings: Res<Settings>, mut windows: ResMut<Windows>) {
+    if !settings.is_changed() {
+        return;
+    }
+    if let Some(window) = windows.get_primary_mut() {
+        if window.mode() != settings.window_mode {
+            window.set_mode(settings.window_mode);
+        }
+    }
+}
+
+/// keeps the size of the window in the settings while it isn't fullscreen.
+pub fn remember_window_size(
+    mut resized: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    mut settings: ResMut<Settings>,
+) {
+    for event in resized.iter() {
+        let is_primary = windows
+            .get_primary()
+            .map_or(false, |window| window.id() == event.id);
+        let size = Vec2::new(event.width, event.height);
+        if is_primary
+            && settings.window_mode == WindowMode::Windowed
+            && settings.window_size != size
+        {
+            settings.window_size = size;
+        }
+    }
+}
+
+/// logs the lines of the settings file that were ignored, once logging is set
+/// up.
+pub fn warn_invalid_lines(invalid: Res<InvalidLines>) {
+    for line in &invalid.0 {
+        warn!("ignoring invalid line in settings: {}", line);
+    }
+}
+
+/// writes the settings and the controls once they stop changing.
+pub fn save_settings(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    input_map: Res<InputMap>,
+    mut changed_at: Local<Option<f64>>,
+) {
+    let now = time.seconds_since_startup();
+    let loaded = settings.is_added() || input_map.is_added();
+    if !loaded && (settings.is_changed() || input_map.is_changed()) {
+        *changed_at = Some(now);
+    }
+    if let Some(at) = *changed_at {
+        if now - at >= SAVE_DELAY {
+            *changed_at = None;
+            if let Err(err) = settings.save(&input_map) {
+                warn!("could not save the settings: {}", err);
+            }
+        }
+    }
+}
@@ -0,0 +1,1302 @@
+//! Deterministic fixed-step simulation of the duel.
+//!
+//! All gameplay rules live here so that the windowed game, the headless gym
+//! and anything else that needs to reproduce a fight step the exact same
+//! state. Nothing in this module reads the wall clock or a thread rng: time
+//! advances in ticks of `DT` seconds and randomness comes from a seeded rng.
+
+use bevy::{
+    math::{const_vec2, Mat2, Vec2},
+    sprite::collide_aabb::collide,
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+pub const TICK_RATE: u32 = 60;
+pub const DT: f32 = 1.0 / TICK_RATE as f32;
+
+/// converts milliseconds into a number of ticks.
+pub const fn ticks(millis: u32) -> u32 {
+    millis * TICK_RATE / 1000
+}
+
+pub const FIGHTER_SIZE: Vec2 = const_vec2!([64.0, 128.0]);
+// the collision box sits a bit lower than the sprite centre
+const FIGHTER_Y_OFFSET: f32 = 15.0;
+const SMALL_COLLIDER_SIZE: Vec2 = const_vec2!([32.0, 32.0]);
+
+// how far a fighter may already sink into a platform and still land on it
+const LANDING_TOLERANCE: f32 = 1.0;
+// landing slower than this, e.g. after stepping off a ledge, is no event
+const MIN_LANDING_SPEED: f32 = 1500.0;
+
+const WALK_SPEED: f32 = 200.0;
+const JUMP_SPEED: f32 = 500.0;
+// velocity lost every tick while airborne
+const GRAVITY: f32 = 500.0;
+const HIT_TICKS: u32 = ticks(3000);
+
+const AI_MIN_X: f32 = -500.0;
+const AI_MAX_X: f32 = 300.0;
+// the ai jumps on average once every AI_JUMP_CHANCE ticks
+const AI_JUMP_CHANCE: u32 = 800;
+const AI_PUNCH_RANGE: f32 = 150.0;
+const AI_PUNCH_CHANCE: u32 = 30;
+// an easy weapon specialist pulls the trigger on one in this many ticks
+const AI_EASY_SHOT_CHANCE: u32 = 40;
+
+const PUNCH_TICKS: u32 = ticks(800);
+// where the gloves rest, in front of the fighter
+const GLOVES_ANCHOR: Vec2 = const_vec2!([20.0, 0.0]);
+const GLOVES_GROWTH: f32 = 0.07;
+// aim used when a punch is thrown without one, e.g. from a keyboard
+const AUTO_AIM_REACH: f32 = 300.0;
+
+const WEAPON_X_OFFSET: f32 = 16.0;
+const WEAPON_Y_OFFSET: f32 = 10.0;
+const GUN_RANGE: f32 = 400.0;
+const GUN_FIRST_SHOT_TICKS: u32 = ticks(256);
+const GUN_RELOAD_TICKS: u32 = ticks(569);
+const SHOTGUN_RELOAD_TICKS: u32 = ticks(5000);
+const SHOTGUN_SPREAD: f32 = 0.2;
+
+// falling platforms drop this much faster every tick
+const PLATFORM_FALL_ACCELERATION: f32 = 0.3;
+// a fighter hit by a hazard is thrown up out of it for this long
+const HAZARD_BOUNCE_TICKS: u32 = ticks(300);
+// how long a laser shows where it is going to fire
+const LASER_WARNING_TICKS: u32 = ticks(1000);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlayerInput {
+    pub left: bool,
+    pub right: bool,
+    pub jump: bool,
+    /// punches for a boxer, fires for a weapon specialist
+    pub attack: bool,
+    /// stands still and takes no hits
+    pub block: bool,
+    /// where the gloves are thrown to, relative to the fighter. zero aims
+    /// at the other fighter.
+    pub aim: Vec2,
+}
+
+impl PlayerInput {
+    pub const ENCODED_LEN: usize = 9;
+
+    /// drops the aim while not attacking, where it has no effect. keeps
+    /// replays small and netplay from rolling back on every mouse move.
+    pub fn without_idle_aim(self) -> Self {
+        PlayerInput {
+            aim: if self.attack { self.aim } else { Vec2::ZERO },
+            ..self
+        }
+    }
+
+    /// appends the input as `ENCODED_LEN` bytes, for packets and replays.
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        let buttons = [self.left, self.right, self.jump, self.attack, self.block];
+        let flags = buttons
+            .iter()
+            .enumerate()
+            .fold(0, |flags, (bit, pressed)| flags | (*pressed as u8) << bit);
+        bytes.push(flags);
+        bytes.extend(self.aim.x.to_le_bytes());
+        bytes.extend(self.aim.y.to_le_bytes());
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<PlayerInput> {
+        let flags = *bytes.first()?;
+        let x = f32::from_le_bytes(read_bytes(bytes, 1)?);
+        let y = f32::from_le_bytes(read_bytes(bytes, 5)?);
+        let pressed = |bit: u8| flags & (1 << bit) != 0;
+        Some(PlayerInput {
+            left: pressed(0),
+            right: pressed(1),
+            jump: pressed(2),
+            attack: pressed(3),
+            block: pressed(4),
+            aim: Vec2::new(x, y),
+        })
+    }
+}
+
+/// the `N` bytes starting at `at`, if there are that many.
+pub fn read_bytes<const N: usize>(bytes: &[u8], at: usize) -> Option<[u8; N]> {
+    bytes.get(at..at + N)?.try_into().ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fighter {
+    Player,
+    Opponent,
+}
+
+impl Fighter {
+    pub const ALL: [Fighter; 2] = [Fighter::Player, Fighter::Opponent];
+
+    pub fn index(self) -> usize {
+        match self {
+            Fighter::Player => 0,
+            Fighter::Opponent => 1,
+        }
+    }
+
+    pub fn other(self) -> Fighter {
+        match self {
+            Fighter::Player => Fighter::Opponent,
+            Fighter::Opponent => Fighter::Player,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Boxer,
+    WeaponSpecialist,
+}
+
+impl Role {
+    pub fn lives(self) -> u32 {
+        match self {
+            Role::Boxer => 5,
+            Role::WeaponSpecialist => 10,
+        }
+    }
+
+    fn jump_ticks(self) -> u32 {
+        match self {
+            Role::Boxer => ticks(1000),
+            Role::WeaponSpecialist => ticks(300),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    Human,
+    Ai,
+    /// stands still and gets its lives back, for training
+    Dummy,
+}
+
+/// how hard the ai fights.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difficulty {
+    /// shoots less often and punches half as much
+    Easy,
+    Normal,
+    /// punches twice as much and blocks punches
+    Hard,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+}
+
+/// who the player fights against. the player is always a human boxer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MatchSettings {
+    pub opponent_role: Role,
+    pub opponent_control: Control,
+    /// only used by the ai
+    pub difficulty: Difficulty,
+}
+
+impl Default for MatchSettings {
+    fn default() -> Self {
+        MatchSettings {
+            opponent_role: Role::WeaponSpecialist,
+            opponent_control: Control::Ai,
+            difficulty: Difficulty::Normal,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Platform {
+    /// the centre
+    pub pos: Vec2,
+    pub size: Vec2,
+    /// can be jumped through from below and the sides, only the top is
+    /// solid
+    pub one_way: bool,
+    pub motion: Motion,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Motion {
+    Static,
+    /// travels to `pos + offset` and back once every `period` ticks
+    Moving {
+        offset: Vec2,
+        period: u32,
+    },
+    /// falls `delay` ticks after a fighter first stands on it and is back
+    /// `respawn` ticks later
+    Falling {
+        delay: u32,
+        respawn: u32,
+    },
+}
+
+impl Platform {
+    pub fn top(&self) -> f32 {
+        self.pos.y + self.size.y / 2.0
+    }
+
+    pub fn bottom(&self) -> f32 {
+        self.pos.y - self.size.y / 2.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HazardKind {
+    Spikes,
+    Lava,
+    /// fires for `active` ticks out of every `period`, sweeping by `sweep`
+    /// while it does
+    Laser {
+        sweep: Vec2,
+        period: u32,
+        active: u32,
+    },
+}
+
+/// hits fighters touching it like a bullet does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hazard {
+    pub kind: HazardKind,
+    /// the centre, where a laser starts its sweep
+    pub pos: Vec2,
+    pub size: Vec2,
+}
+
+impl Hazard {
+    /// the centre of the hazard on `tick`, none while a laser is off.
+    pub fn pos_at(&self, tick: u64) -> Option<Vec2> {
+        match self.kind {
+            HazardKind::Spikes | HazardKind::Lava => Some(self.pos),
+            HazardKind::Laser {
+                sweep,
+                period,
+                active,
+            } => {
+                let phase = (tick % period.max(1) as u64) as u32;
+                (phase < active).then(|| self.pos + sweep * (phase as f32 / active as f32))
+            }
+        }
+    }
+
+    /// whether a laser is about to fire.
+    pub fn is_warning(&self, tick: u64) -> bool {
+        match self.kind {
+            HazardKind::Laser { period, .. } => {
+                let phase = (tick % period.max(1) as u64) as u32;
+                phase + LASER_WARNING_TICKS >= period
+            }
+            _ => false,
+        }
+    }
+}
+
+/// the part of a stage that takes part in the fight.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arena {
+    pub platforms: Vec<Platform>,
+    pub hazards: Vec<Hazard>,
+    /// where the fighters start and respawn, indexed by `Fighter::index`
+    pub spawns: [Vec2; 2],
+    /// fighters leaving this rectangle are put back to their spawn
+    pub blast_min: Vec2,
+    pub blast_max: Vec2,
+    /// how the bullets of each weapon fly, by `Weapon`
+    pub projectiles: [Projectile; 2],
+}
+
+impl Default for Arena {
+    /// the original arena, a single slab of ground.
+    fn default() -> Self {
+        Arena {
+            platforms: vec![Platform {
+                pos: Vec2::new(0.0, -200.0),
+                size: Vec2::new(700.0, 50.0),
+                one_way: false,
+                motion: Motion::Static,
+            }],
+            hazards: Vec::new(),
+            spawns: [Vec2::new(0.0, 200.0), Vec2::new(300.0, 200.0)],
+            blast_min: Vec2::new(-1500.0, -200.0),
+            blast_max: Vec2::new(1500.0, 1500.0),
+            projectiles: Default::default(),
+        }
+    }
+}
+
+impl Arena {
+    pub fn in_blast_zone(&self, pos: Vec2) -> bool {
+        pos.x >= self.blast_min.x
+            && pos.y >= self.blast_min.y
+            && pos.x <= self.blast_max.x
+            && pos.y <= self.blast_max.y
+    }
+
+    pub fn projectile(&self, weapon: Weapon) -> &Projectile {
+        &self.projectiles[weapon as usize]
+    }
+}
+
+/// a platform during the fight.
+#[derive(Clone, Debug)]
+pub struct PlatformState {
+    pub pos: Vec2,
+    /// how far it moved during the last tick, fighters standing on it move
+    /// along
+    pub moved: Vec2,
+    /// ticks since a fighter first stood on a falling platform
+    stood_ticks: u32,
+    /// ticks since a falling platform gave way, it doesn't hold anyone then
+    fall_ticks: u32,
+}
+
+impl PlatformState {
+    /// a falling platform that was stood on and is about to give way.
+    pub fn is_crumbling(&self) -> bool {
+        self.stood_ticks > 0
+    }
+
+    pub fn is_falling(&self) -> bool {
+        self.fall_ticks > 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Weapon {
+    Gun,
+    Shotgun,
+}
+
+impl Weapon {
+    pub const ALL: [Weapon; 2] = [Weapon::Gun, Weapon::Shotgun];
+
+    /// ticks between two shots
+    pub fn reload_ticks(self) -> u32 {
+        match self {
+            Weapon::Gun => GUN_RELOAD_TICKS,
+            Weapon::Shotgun => SHOTGUN_RELOAD_TICKS,
+        }
+    }
+}
+
+/// how the bullets of a weapon fly, see `weapons` for the files it is
+/// loaded from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Projectile {
+    /// seconds since the shot and the speed in units per second from then
+    /// on, linear in between and held after the last key
+    pub speed: Vec<(f32, f32)>,
+    /// units per second squared pulling it down, makes it fly in an arc
+    pub gravity: f32,
+    /// radians per second it turns towards the fighter it was fired at
+    pub turn_rate: f32,
+    /// times it bounces off solid platforms, it is stopped by the next one
+    pub ricochets: u32,
+    /// fighters it hits and flies on through, a guard stops it anyway
+    pub pierces: u32,
+    pub lifetime_ticks: u32,
+}
+
+impl Default for Projectile {
+    /// a bullet flying straight ahead.
+    fn default() -> Self {
+        Projectile {
+            speed: vec![(0.0, 360.0)],
+            gravity: 0.0,
+            turn_rate: 0.0,
+            ricochets: 0,
+            pierces: 0,
+            lifetime_ticks: ticks(4000),
+        }
+    }
+}
+
+impl Projectile {
+    /// the speed in units per second, `seconds` after the shot.
+    pub fn speed_at(&self, seconds: f32) -> f32 {
+        let keys = &self.speed;
+        match keys.iter().position(|(time, _)| *time > seconds) {
+            None => keys.last().map_or(0.0, |(_, speed)| *speed),
+            Some(0) => keys[0].1,
+            Some(i) => {
+                let ((from_time, from), (to_time, to)) = (keys[i - 1], keys[i]);
+                // `to_time` is the first time past `seconds`, so it is also
+                // past `from_time`
+                let t = (seconds - from_time) / (to_time - from_time);
+                from + (to - from) * t
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SimEvent {
+    Punch {
+        fighter: Fighter,
+        pos: Vec2,
+    },
+    Shot {
+        fighter: Fighter,
+        pos: Vec2,
+        weapon: Weapon,
+        /// where the weapon points
+        dir: Vec2,
+    },
+    /// `pos` is where the fighter was hit, at the gloves or the bullet
+    Hit {
+        fighter: Fighter,
+        pos: Vec2,
+        cause: HitCause,
+    },
+    /// `pos` is at the feet, `speed` how fast the fighter came down
+    Land {
+        fighter: Fighter,
+        pos: Vec2,
+        speed: f32,
+    },
+    /// the fighter lost their last life
+    KnockOut {
+        fighter: Fighter,
+        pos: Vec2,
+    },
+    /// a bullet struck a solid platform or a guard, `normal` points away
+    /// from what it struck
+    Impact {
+        pos: Vec2,
+        normal: Vec2,
+        /// bounced off instead of being stopped
+        ricochet: bool,
+    },
+}
+
+impl SimEvent {
+    /// where it happened.
+    pub fn pos(&self) -> Vec2 {
+        match *self {
+            SimEvent::Punch { pos, .. }
+            | SimEvent::Shot { pos, .. }
+            | SimEvent::Hit { pos, .. }
+            | SimEvent::Land { pos, .. }
+            | SimEvent::KnockOut { pos, .. }
+            | SimEvent::Impact { pos, .. } => pos,
+        }
+    }
+
+    /// who it happened to, if anyone.
+    pub fn fighter(&self) -> Option<Fighter> {
+        match *self {
+            SimEvent::Punch { fighter, .. }
+            | SimEvent::Shot { fighter, .. }
+            | SimEvent::Hit { fighter, .. }
+            | SimEvent::Land { fighter, .. }
+            | SimEvent::KnockOut { fighter, .. } => Some(fighter),
+            SimEvent::Impact { .. } => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HitCause {
+    Punch,
+    Bullet,
+    Hazard,
+}
+
+#[derive(Clone, Debug)]
+pub struct GlovesState {
+    pub pos: Vec2,
+    pub offset: Vec2,
+    pub punch_ticks: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct WeaponState {
+    pub pos: Vec2,
+    /// rotation around z in radians
+    pub angle: f32,
+    pub is_active: bool,
+    pub reload_ticks: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct FighterState {
+    pub role: Role,
+    pub control: Control,
+    pub pos: Vec2,
+    pub vel: Vec2,
+    pub lives: u32,
+    pub hit_ticks: u32,
+    pub on_ground: bool,
+    /// only guards against what comes from the side the fighter faces
+    pub blocking: bool,
+    /// 1 when facing right, -1 when facing left
+    pub facing: f32,
+    /// only used by boxers
+    pub gloves: GlovesState,
+    /// only used by weapon specialists
+    pub gun: WeaponState,
+    pub shotgun: WeaponState,
+    respawn: Vec2,
+    jump_ticks: u32,
+    jump_held: bool,
+}
+
+impl FighterState {
+    fn new(role: Role, control: Control, spawn: Vec2) -> Self {
+        FighterState {
+            role,
+            control,
+            pos: spawn,
+            vel: Vec2::ZERO,
+            lives: role.lives(),
+            hit_ticks: 0,
+            on_ground: false,
+            blocking: false,
+            // towards the middle of the stage until the first tick
+            facing: if spawn.x > 0.0 { -1.0 } else { 1.0 },
+            gloves: GlovesState {
+                pos: spawn,
+                offset: Vec2::ZERO,
+                punch_ticks: 0,
+            },
+            gun: WeaponState {
+                pos: spawn,
+                angle: 0.0,
+                is_active: role == Role::WeaponSpecialist,
+                reload_ticks: GUN_FIRST_SHOT_TICKS,
+            },
+            shotgun: WeaponState {
+                pos: spawn,
+                angle: 0.0,
+                is_active: false,
+                reload_ticks: SHOTGUN_RELOAD_TICKS,
+            },
+            respawn: spawn,
+            jump_ticks: 0,
+            jump_held: false,
+        }
+    }
+
+    pub fn is_hit(&self) -> bool {
+        self.hit_ticks > 0
+    }
+
+    /// whether `pos` is on the side the fighter faces.
+    pub fn faces(&self, pos: Vec2) -> bool {
+        (pos.x - self.pos.x) * self.facing >= 0.0
+    }
+
+    /// `offset` to the right of the fighter, mirrored when facing left.
+    pub fn anchor(&self, offset: Vec2) -> Vec2 {
+        self.pos + Vec2::new(offset.x * self.facing, offset.y)
+    }
+
+    pub fn weapon(&self, weapon: Weapon) -> &WeaponState {
+        match weapon {
+            Weapon::Gun => &self.gun,
+            Weapon::Shotgun => &self.shotgun,
+        }
+    }
+
+    /// how far a hit has taken the fighter's current life, from 0 to 1. the
+    /// life is lost when the hit wears off.
+    pub fn hit_progress(&self) -> f32 {
+        if self.is_hit() {
+            1.0 - self.hit_ticks as f32 / HIT_TICKS as f32
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BulletState {
+    pub id: u32,
+    pub owner: Fighter,
+    pub weapon: Weapon,
+    pub pos: Vec2,
+    /// distance travelled every tick
+    pub vel: Vec2,
+    /// where it is headed, turned by homing and ricochets
+    pub dir: Vec2,
+    /// the part of `vel` gravity added
+    pub fall: Vec2,
+    pub lifetime_ticks: u32,
+    /// bounces off solid platforms left
+    pub ricochets: u32,
+    /// fighters it may still fly through
+    pub pierces: u32,
+    /// the fighter it is flying through, it doesn't hit them again until it
+    /// is out
+    pub pierced: Option<Fighter>,
+}
+
+#[derive(Clone)]
+pub struct Simulation {
+    pub tick: u64,
+    /// the seed the rng started from, enough to replay the match together
+    /// with the settings and inputs
+    pub seed: u64,
+    pub settings: MatchSettings,
+    pub arena: Arena,
+    /// indexed like `arena.platforms`
+    pub platforms: Vec<PlatformState>,
+    /// indexed by `Fighter::index`
+    pub fighters: [FighterState; 2],
+    pub bullets: Vec<BulletState>,
+    /// events raised during the last call to `step`
+    pub events: Vec<SimEvent>,
+    next_bullet_id: u32,
+    rng: StdRng,
+}
+
+impl Simulation {
+    /// a fight in the original arena.
+    pub fn new(seed: u64, settings: MatchSettings) -> Self {
+        Simulation::with_arena(seed, settings, Arena::default())
+    }
+
+    pub fn with_arena(seed: u64, settings: MatchSettings, arena: Arena) -> Self {
+        let [player_spawn, opponent_spawn] = arena.spawns;
+        Simulation {
+            tick: 0,
+            seed,
+            settings,
+            fighters: [
+                FighterState::new(Role::Boxer, Control::Human, player_spawn),
+                FighterState::new(
+                    settings.opponent_role,
+                    settings.opponent_control,
+                    opponent_spawn,
+                ),
+            ],
+            platforms: arena
+                .platforms
+                .iter()
+                .map(|platform| PlatformState {
+                    pos: platform.pos,
+                    moved: Vec2::ZERO,
+                    stood_ticks: 0,
+                    fall_ticks: 0,
+                })
+                .collect(),
+            arena,
+            bullets: Vec::new(),
+            events: Vec::new(),
+            next_bullet_id: 0,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn fighter(&self, fighter: Fighter) -> &FighterState {
+        &self.fighters[fighter.index()]
+    }
+
+    /// advances the fight by one tick. inputs are indexed by
+    /// `Fighter::index`, the input of a fighter not controlled by a human is
+    /// ignored.
+    pub fn step(&mut self, inputs: &[PlayerInput; 2]) {
+        self.events.clear();
+        self.tick += 1;
+
+        let mut inputs = *inputs;
+        for fighter in Fighter::ALL {
+            if self.fighter(fighter).control != Control::Human {
+                inputs[fighter.index()] = self.ai_input(fighter);
+            }
+            let input = &mut inputs[fighter.index()];
+            self.fighters[fighter.index()].blocking = input.block;
+            if input.block {
+                *input = PlayerInput {
+                    block: true,
+                    ..Default::default()
+                };
+            }
+        }
+
+        self.step_platforms();
+        for fighter in Fighter::ALL {
+            self.step_fighter(fighter, &inputs[fighter.index()]);
+        }
+        for fighter in Fighter::ALL {
+            self.step_gloves(fighter, &inputs[fighter.index()]);
+        }
+        for fighter in Fighter::ALL {
+            self.step_weapons(fighter, &inputs[fighter.index()]);
+        }
+        self.step_bullets();
+    }
+
+    /// the fighter that won the match, if it is over.
+    pub fn winner(&self) -> Option<Fighter> {
+        Fighter::ALL
+            .into_iter()
+            .find(|fighter| self.fighter(*fighter).lives == 0)
+            .map(Fighter::other)
+    }
+
+    /// what the ai presses this tick.
+    fn ai_input(&mut self, fighter: Fighter) -> PlayerInput {
+        let me = &self.fighters[fighter.index()];
+        let them = &self.fighters[fighter.other().index()];
+        if me.control == Control::Dummy {
+            return PlayerInput::default();
+        }
+
+        let difficulty = self.settings.difficulty;
+        let in_reach = me.pos.distance(them.pos) < AI_PUNCH_RANGE;
+        let walk = self.rng.gen_range(0..2);
+        let jump = self.rng.gen_range(0..AI_JUMP_CHANCE);
+        let attack = match (me.role, difficulty) {
+            (Role::WeaponSpecialist, Difficulty::Easy) => {
+                self.rng.gen_range(0..AI_EASY_SHOT_CHANCE) == 0
+            }
+            (Role::WeaponSpecialist, _) => true,
+            (Role::Boxer, _) => {
+                let chance = match difficulty {
+                    Difficulty::Easy => AI_PUNCH_CHANCE * 2,
+                    Difficulty::Normal => AI_PUNCH_CHANCE,
+                    Difficulty::Hard => AI_PUNCH_CHANCE / 2,
+                };
+                in_reach && self.rng.gen_range(0..chance) == 0
+            }
+        };
+        let block = difficulty == Difficulty::Hard
+            && in_reach
+            && them.role == Role::Boxer
+            && them.gloves.punch_ticks > 0;
+
+        PlayerInput {
+            left: walk == 0 && me.pos.x >= AI_MIN_X,
+            right: walk == 1 && me.pos.x <= AI_MAX_X,
+            jump: jump == 1,
+            attack,
+            block,
+            aim: Vec2::ZERO,
+        }
+    }
+
+    fn step_fighter(&mut self, fighter: Fighter, input: &PlayerInput) {
+        let (me, them) = split(&mut self.fighters, fighter);
+        me.vel.y -= GRAVITY;
+
+        if me.blocking {
+            me.vel.x = 0.0;
+        }
+        if input.left {
+            me.vel.x = -WALK_SPEED;
+        }
+        if input.right {
+            me.vel.x = WALK_SPEED;
+        }
+        // a guard stays the way it was put up, otherwise the fighter turns
+        // to where they walk or else to the other fighter
+        if !me.blocking && !me.is_hit() {
+            if input.left != input.right {
+                me.facing = if input.left { -1.0 } else { 1.0 };
+            } else if me.gloves.punch_ticks == 0 && them.pos.x != me.pos.x {
+                me.facing = (them.pos.x - me.pos.x).signum();
+            }
+        }
+
+        let jump_pressed = input.jump && !me.jump_held;
+        me.jump_held = input.jump;
+        if jump_pressed && me.on_ground {
+            me.jump_ticks = me.role.jump_ticks();
+        }
+        if me.jump_ticks > 0 {
+            me.jump_ticks -= 1;
+            me.vel.y = JUMP_SPEED;
+        }
+
+        let (was_on_ground, fall_speed) = (me.on_ground, -me.vel.y);
+        let ground = move_fighter(
+            &mut me.pos,
+            &mut me.vel,
+            &self.arena.platforms,
+            &self.platforms,
+        );
+        me.on_ground = ground.is_some();
+        if me.on_ground && !was_on_ground && fall_speed >= MIN_LANDING_SPEED {
+            self.events.push(SimEvent::Land {
+                fighter,
+                pos: fighter_box(me.pos) - Vec2::new(0.0, FIGHTER_SIZE.y / 2.0),
+                speed: fall_speed,
+            });
+        }
+        if let Some(i) = ground {
+            let state = &mut self.platforms[i];
+            if matches!(self.arena.platforms[i].motion, Motion::Falling { .. })
+                && state.stood_ticks == 0
+            {
+                state.stood_ticks = 1;
+            }
+        }
+
+        let hit_box = fighter_box(me.pos).extend(0.0);
+        let touches = |pos: Vec2| {
+            collide(hit_box, FIGHTER_SIZE, pos.extend(0.0), SMALL_COLLIDER_SIZE).is_some()
+        };
+        // what comes from behind gets through the guard, a bullet comes from
+        // the other way than it flies
+        let guarded = |from: Vec2| me.blocking && me.faces(from);
+        for bullet in self.bullets.iter_mut() {
+            if bullet.pierced == Some(fighter) && !touches(bullet.pos) {
+                bullet.pierced = None;
+            }
+        }
+        // a bullet is used up by the first fighter it reaches unless it
+        // pierces, a guard or a fighter who is already hit stop it without
+        // being hit
+        let shot = self
+            .bullets
+            .iter()
+            .position(|bullet| {
+                me.lives > 0
+                    && bullet.owner != fighter
+                    && bullet.pierced != Some(fighter)
+                    && touches(bullet.pos)
+            })
+            .and_then(|i| {
+                let bullet = &mut self.bullets[i];
+                let (pos, vel) = (bullet.pos, bullet.vel);
+                let blocked = guarded(me.pos - vel);
+                if bullet.pierces > 0 && !blocked {
+                    bullet.pierces -= 1;
+                    bullet.pierced = Some(fighter);
+                } else {
+                    self.bullets.remove(i);
+                }
+                if blocked || me.is_hit() {
+                    self.events.push(SimEvent::Impact {
+                        pos,
+                        normal: -vel.normalize_or_zero(),
+                        ricochet: false,
+                    });
+                    None
+                } else {
+                    Some((pos, HitCause::Bullet))
+                }
+            });
+
+        if me.lives > 0 && !me.is_hit() {
+            let punched =
+                (them.role == Role::Boxer && touches(them.gloves.pos) && !guarded(them.pos))
+                    .then(|| (them.gloves.pos, HitCause::Punch));
+            let hazard = !me.blocking
+                && self.arena.hazards.iter().any(|hazard| {
+                    hazard.pos_at(self.tick).map_or(false, |pos| {
+                        collide(hit_box, FIGHTER_SIZE, pos.extend(0.0), hazard.size).is_some()
+                    })
+                });
+            if hazard {
+                me.jump_ticks = HAZARD_BOUNCE_TICKS;
+            }
+            let hit = punched
+                .or(shot)
+                .or_else(|| hazard.then(|| (me.pos, HitCause::Hazard)));
+            if let Some((pos, cause)) = hit {
+                me.hit_ticks = HIT_TICKS;
+                self.events.push(SimEvent::Hit {
+                    fighter,
+                    pos,
+                    cause,
+                });
+            }
+        } else if me.is_hit() {
+            me.hit_ticks -= 1;
+            if me.hit_ticks == 0 {
+                me.lives -= 1;
+                if me.lives == 0 && me.control == Control::Dummy {
+                    me.lives = me.role.lives();
+                } else if me.lives == 0 {
+                    self.events.push(SimEvent::KnockOut {
+                        fighter,
+                        pos: me.pos,
+                    });
+                }
+            }
+        }
+
+        if !self.arena.in_blast_zone(me.pos) {
+            me.pos = me.respawn;
+            me.vel = Vec2::ZERO;
+        }
+    }
+
+    fn step_platforms(&mut self) {
+        for (state, platform) in self.platforms.iter_mut().zip(self.arena.platforms.iter()) {
+            let before = state.pos;
+            match platform.motion {
+                Motion::Static => (),
+                Motion::Moving { offset, period } => {
+                    state.pos = platform.pos + offset * ping_pong(self.tick, period);
+                }
+                Motion::Falling { delay, respawn } => {
+                    if state.fall_ticks > 0 {
+                        state.fall_ticks += 1;
+                        let t = state.fall_ticks as f32;
+                        state.pos.y = platform.pos.y - 0.5 * PLATFORM_FALL_ACCELERATION * t * t;
+                        if state.fall_ticks > respawn {
+                            state.fall_ticks = 0;
+                            state.pos = platform.pos;
+                        }
+                    } else if state.stood_ticks > 0 {
+                        state.stood_ticks += 1;
+                        if state.stood_ticks > delay {
+                            state.stood_ticks = 0;
+                            state.fall_ticks = 1;
+                        }
+                    }
+                }
+            }
+            state.moved = state.pos - before;
+        }
+    }
+
+    fn step_gloves(&mut self, fighter: Fighter, input: &PlayerInput) {
+        let (me, them) = split(&mut self.fighters, fighter);
+        if me.role != Role::Boxer {
+            me.gloves.pos = me.pos;
+            return;
+        }
+
+        if me.gloves.punch_ticks > 0 {
+            let gloves = &mut me.gloves;
+            gloves.punch_ticks -= 1;
+            gloves.offset += gloves.offset * GLOVES_GROWTH;
+            if gloves.punch_ticks == 0 {
+                gloves.offset = Vec2::ZERO;
+            }
+        } else if input.attack {
+            let aim = if input.aim == Vec2::ZERO {
+                (them.pos - me.pos).normalize_or_zero() * AUTO_AIM_REACH
+            } else {
+                input.aim
+            };
+            // punching behind turns the fighter around
+            if aim.x != 0.0 {
+                me.facing = aim.x.signum();
+            }
+            me.gloves.punch_ticks = PUNCH_TICKS;
+            me.gloves.offset = aim / 100.0;
+            self.events.push(SimEvent::Punch {
+                fighter,
+                pos: me.pos,
+            });
+        }
+        me.gloves.pos = me.anchor(GLOVES_ANCHOR) + me.gloves.offset;
+    }
+
+    fn step_weapons(&mut self, fighter: Fighter, input: &PlayerInput) {
+        let (me, them) = split(&mut self.fighters, fighter);
+        let armed = me.lives > 0 && input.attack;
+        // pulling the trigger turns the fighter to the target
+        if armed && me.role == Role::WeaponSpecialist && them.pos.x != me.pos.x {
+            me.facing = (them.pos.x - me.pos.x).signum();
+        }
+        let pos = me.anchor(Vec2::new(WEAPON_X_OFFSET, -WEAPON_Y_OFFSET));
+        let to_target = them.pos - pos;
+        // the weapons point at the other fighter while they are in front
+        let aim = if me.faces(them.pos) {
+            to_target
+        } else {
+            Vec2::new(me.facing, 0.0)
+        };
+        let angle = f32::atan2(-aim.y, -aim.x);
+        let is_specialist = me.role == Role::WeaponSpecialist;
+        let in_range = to_target.x.abs() < GUN_RANGE;
+
+        for (weapon, is_active) in [(&mut me.gun, !in_range), (&mut me.shotgun, in_range)] {
+            weapon.pos = pos;
+            weapon.angle = angle;
+            weapon.is_active = is_specialist && is_active;
+            weapon.reload_ticks = weapon.reload_ticks.saturating_sub(1);
+        }
+
+        let dir = aim.normalize_or_zero();
+        // only one of the weapons is active at a time
+        let mut shot = None;
+        if armed && me.gun.is_active && me.gun.reload_ticks == 0 {
+            me.gun.reload_ticks = Weapon::Gun.reload_ticks();
+            shot = Some((Weapon::Gun, vec![dir]));
+        }
+        if armed && me.shotgun.is_active && me.shotgun.reload_ticks == 0 {
+            me.shotgun.reload_ticks = Weapon::Shotgun.reload_ticks();
+            let spreads = [0.0, SHOTGUN_SPREAD, -SHOTGUN_SPREAD];
+            shot = Some((
+                Weapon::Shotgun,
+                spreads
+                    .map(|spread| Mat2::from_angle(spread) * dir)
+                    .to_vec(),
+            ));
+        }
+
+        if let Some((weapon, directions)) = shot {
+            for bullet_dir in directions {
+                self.spawn_bullet(fighter, weapon, pos, bullet_dir);
+            }
+            self.events.push(SimEvent::Shot {
+                fighter,
+                pos,
+                weapon,
+                dir,
+            });
+        }
+    }
+
+    fn spawn_bullet(&mut self, owner: Fighter, weapon: Weapon, pos: Vec2, dir: Vec2) {
+        let projectile = self.arena.projectile(weapon);
+        self.bullets.push(BulletState {
+            id: self.next_bullet_id,
+            owner,
+            weapon,
+            pos,
+            vel: dir * projectile.speed_at(0.0) * DT,
+            dir,
+            fall: Vec2::ZERO,
+            lifetime_ticks: projectile.lifetime_ticks,
+            ricochets: projectile.ricochets,
+            pierces: projectile.pierces,
+            pierced: None,
+        });
+        self.next_bullet_id = self.next_bullet_id.wrapping_add(1);
+    }
+
+    /// steers, accelerates and moves the bullets the way their weapon's
+    /// projectile flies, bounces them off or stops them at solid platforms
+    /// and drops the ones that ran out of lifetime or left the stage.
+    fn step_bullets(&mut self) {
+        for bullet in self.bullets.iter_mut() {
+            let projectile = self.arena.projectile(bullet.weapon);
+            let target = &self.fighters[bullet.owner.other().index()];
+            if projectile.turn_rate > 0.0 && target.lives > 0 {
+                let to_target = fighter_box(target.pos) - bullet.pos;
+                let turn = bullet
+                    .dir
+                    .perp_dot(to_target)
+                    .atan2(bullet.dir.dot(to_target));
+                let max_turn = projectile.turn_rate * DT;
+                bullet.dir = Mat2::from_angle(turn.clamp(-max_turn, max_turn)) * bullet.dir;
+            }
+            bullet.fall.y -= projectile.gravity * DT * DT;
+            let age = projectile
+                .lifetime_ticks
+                .saturating_sub(bullet.lifetime_ticks);
+            let speed = projectile.speed_at(age as f32 * DT) * DT;
+            bullet.vel = bullet.dir * speed + bullet.fall;
+
+            let before = bullet.pos;
+            bullet.pos += bullet.vel;
+            bullet.lifetime_ticks = bullet.lifetime_ticks.saturating_sub(1);
+
+            let platform =
+                match solid_platform_at(&self.arena.platforms, &self.platforms, bullet.pos) {
+                    Some(platform) => platform,
+                    None => continue,
+                };
+            // the side it came in through
+            let normal = if before.y >= platform.top() {
+                Vec2::Y
+            } else if before.y <= platform.bottom() {
+                -Vec2::Y
+            } else if before.x < platform.pos.x {
+                -Vec2::X
+            } else {
+                Vec2::X
+            };
+            let ricochet = bullet.ricochets > 0;
+            if ricochet {
+                bullet.ricochets -= 1;
+                bullet.pos = before;
+                let reflect = |v: Vec2| v - 2.0 * v.dot(normal) * normal;
+                bullet.vel = reflect(bullet.vel);
+                bullet.dir = reflect(bullet.dir);
+                bullet.fall = reflect(bullet.fall);
+            } else {
+                bullet.lifetime_ticks = 0;
+            }
+            self.events.push(SimEvent::Impact {
+                pos: before,
+                normal,
+                ricochet,
+            });
+        }
+        let arena = &self.arena;
+        self.bullets
+            .retain(|bullet| bullet.lifetime_ticks > 0 && arena.in_blast_zone(bullet.pos));
+    }
+}
+
+/// borrows `fighter` mutably together with the other fighter.
+fn split(
+    fighters: &mut [FighterState; 2],
+    fighter: Fighter,
+) -> (&mut FighterState, &mut FighterState) {
+    let [player, opponent] = fighters;
+    match fighter {
+        Fighter::Player => (player, opponent),
+        Fighter::Opponent => (opponent, player),
+    }
+}
+
+/// goes from 0 to 1 and back to 0 once every `period` ticks.
+fn ping_pong(tick: u64, period: u32) -> f32 {
+    let t = (tick % period.max(1) as u64) as f32 / period.max(1) as f32;
+    1.0 - (2.0 * t - 1.0).abs()
+}
+
+fn fighter_box(pos: Vec2) -> Vec2 {
+    Vec2::new(pos.x, pos.y - FIGHTER_Y_OFFSET)
+}
+
+/// the platform `pos` is inside of, where it is right now. one-way and
+/// falling platforms let bullets through.
+fn solid_platform_at(
+    platforms: &[Platform],
+    states: &[PlatformState],
+    pos: Vec2,
+) -> Option<Platform> {
+    platforms
+        .iter()
+        .zip(states.iter())
+        .filter(|(platform, state)| !platform.one_way && !state.is_falling())
+        .map(|(platform, state)| Platform {
+            pos: state.pos,
+            ..*platform
+        })
+        .find(|platform| {
+            let delta = (pos - platform.pos).abs();
+            delta.x < platform.size.x / 2.0 && delta.y < platform.size.y / 2.0
+        })
+}
+
+/// integrates a fighter for one tick and keeps it out of the platforms.
+/// returns the index of the platform it is standing on.
+fn move_fighter(
+    pos: &mut Vec2,
+    vel: &mut Vec2,
+    platforms: &[Platform],
+    states: &[PlatformState],
+) -> Option<usize> {
+    let before = fighter_box(*pos);
+    *pos += *vel * DT;
+
+    let mut ground = None;
+    for (i, (platform, state)) in platforms.iter().zip(states.iter()).enumerate() {
+        if state.is_falling() {
+            continue;
+        }
+        let platform = Platform {
+            pos: state.pos,
+            ..*platform
+        };
+        let reach = (FIGHTER_SIZE + platform.size) / 2.0;
+        let delta = fighter_box(*pos) - platform.pos;
+        if delta.x.abs() >= reach.x || delta.y.abs() > reach.y {
+            continue;
+        }
+
+        // compared to where the platform was, a platform moving up would
+        // otherwise push the fighters on it aside
+        let bottom_before = before.y - FIGHTER_SIZE.y / 2.0;
+        let top_before = before.y + FIGHTER_SIZE.y / 2.0;
+        if vel.y <= 0.0 && bottom_before >= platform.top() - state.moved.y - LANDING_TOLERANCE {
+            pos.y += reach.y - delta.y;
+            pos.x += state.moved.x;
+            vel.y = 0.0;
+            ground = Some(i);
+        } else if platform.one_way {
+            continue;
+        } else if vel.y > 0.0 && top_before <= platform.bottom() - state.moved.y + LANDING_TOLERANCE
+        {
+            // bumped the head
+            pos.y -= reach.y + delta.y;
+            vel.y = 0.0;
+        } else {
+            pos.x += reach.x.copysign(delta.x) - delta.x;
+            vel.x = 0.0;
+        }
+    }
+    ground
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // walks back and forth, jumps and punches now and then
+    fn scripted_input(tick: u64) -> PlayerInput {
+        PlayerInput {
+            left: tick % 120 < 50,
+            right: tick % 120 >= 70,
+            jump: tick % 90 == 0,
+            attack: tick % 25 < 3,
+            block: tick % 200 > 185,
+            aim: Vec2::new(1.0, 0.2),
+        }
+    }
+
+    #[test]
+    fn inputs_survive_encoding() {
+        for tick in 0..400 {
+            let input = scripted_input(tick);
+            let mut bytes = Vec::new();
+            input.encode(&mut bytes);
+            assert_eq!(bytes.len(), PlayerInput::ENCODED_LEN);
+            assert_eq!(PlayerInput::decode(&bytes), Some(input));
+            assert_eq!(PlayerInput::decode(&bytes[..bytes.len() - 1]), None);
+        }
+    }
+
+    #[test]
+    fn same_seed_and_inputs_play_the_same_match() {
+        let settings = MatchSettings {
+            opponent_control: Control::Ai,
+            ..Default::default()
+        };
+        let play = || {
+            let mut sim = Simulation::new(7, settings);
+            let mut events = Vec::new();
+            for tick in 0..3000 {
+                sim.step(&[scripted_input(tick), PlayerInput::default()]);
+                events.extend(sim.events.iter().copied());
+            }
+            (sim, events)
+        };
+        let (first, first_events) = play();
+        let (second, second_events) = play();
+        assert_eq!(first.tick, second.tick);
+        assert_eq!(
+            format!("{:?}", first.fighters),
+            format!("{:?}", second.fighters)
+        );
+        assert_eq!(
+            format!("{:?}", first.bullets),
+            format!("{:?}", second.bullets)
+        );
+        assert_eq!(
+            format!("{:?}", first.platforms),
+            format!("{:?}", second.platforms)
+        );
+        assert_eq!(first_events, second_events);
+        assert!(!first_events.is_empty());
+    }
+}
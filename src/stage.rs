@@ -0,0 +1,634 @@
+//! Stages, loaded from `assets/stages/<name>.stage` when a match starts.
+//!
+//! A stage file has one `key = value` line per setting:
+//!
+//! ```text
+//! name = Towers
+//! background = 0.45 0.6 0.95
+//! layer = stages/hills.png 0.5 0 -150 1600 400
+//! platform = 0 -220 900 50
+//! one_way = -250 -90 200 20
+//! one_way = 250 -90 200 20 moves 0 150 4
+//! one_way = 0 40 150 20 falls 0.5 3
+//! lava = 0 -300 2000 80
+//! laser = -600 200 20 400 1200 0 6 2
+//! player_spawn = -200 100
+//! opponent_spawn = 250 100
+//! blast_zone = -1200 -450 1200 1200
+//! camera = -1000 -400 1000 800
+//! music = audio/music/fight_night.wav
+//! tension_music = audio/music/tension.wav
+//! ```
+//!
+//! `background` is the color behind everything. `layer` draws an image
+//! behind the fighters: the image, how much it follows the camera (0 stays
+//! put, 1 moves along), then centre and size. `platform` and `one_way` are
+//! rectangles given by centre and size; one-way platforms can be jumped
+//! through from below. A platform followed by `moves` travels by the given
+//! offset and back in the given seconds, one followed by `falls` gives way
+//! the given seconds after someone stands on it and comes back after the
+//! second number of seconds.
+//!
+//! `spikes` and `lava` hurt whoever touches the rectangle. `laser` is a
+//! rectangle that fires for the last number of seconds out of every period
+//! (the number before), sweeping by the given offset while it does. Fighters
+//! leaving the `blast_zone` (left, bottom, right, top) respawn and the camera
+//! never shows anything outside of `camera`, given the same way. Later lines
+//! draw on top of earlier ones.
+//!
+//! `music` plays during the fight and `tension_music` once someone is down to
+//! their last lives. `title_music`, `victory_music` and `defeat_music` play on
+//! the title screen while the stage is picked and after the match. Any of them
+//! can be left out for silence.
+
+use bevy::prelude::*;
+use bevy::render::camera::CameraPlugin;
+
+use std::io;
+
+use crate::sim::{self, Arena, Hazard, HazardKind, Motion, Platform, Projectile};
+use crate::weapons;
+use crate::Sim;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub const STAGE_DIR: &str = "assets/stages";
+
+// the browser build has no file system to look for stages in
+#[cfg(target_arch = "wasm32")]
+const SHIPPED_STAGES: [(&str, &str); 4] = [
+    ("arena", include_str!("../assets/stages/arena.stage")),
+    ("islands", include_str!("../assets/stages/islands.stage")),
+    ("rooftops", include_str!("../assets/stages/rooftops.stage")),
+    ("towers", include_str!("../assets/stages/towers.stage")),
+];
+
+pub const DEFAULT_STAGE: &str = "arena";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    /// asset path of the image
+    pub image: String,
+    /// how much the layer follows the camera, 0 stays put, 1 moves along
+    pub parallax: f32,
+    pub pos: Vec2,
+    pub size: Vec2,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stage {
+    /// the file name without extension
+    pub id: String,
+    pub name: String,
+    pub arena: Arena,
+    pub background: [f32; 3],
+    pub layers: Vec<Layer>,
+    /// the part of the stage the camera may show
+    pub camera_min: Vec2,
+    pub camera_max: Vec2,
+    /// asset paths of the music, by `Track`
+    pub music: [Option<String>; 5],
+}
+
+/// what a piece of music plays for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Track {
+    Title,
+    Fight,
+    Tension,
+    Victory,
+    Defeat,
+}
+
+impl Track {
+    pub const ALL: [Track; 5] = [
+        Track::Title,
+        Track::Fight,
+        Track::Tension,
+        Track::Victory,
+        Track::Defeat,
+    ];
+
+    /// the key of the track in stage files
+    fn key(self) -> &'static str {
+        match self {
+            Track::Title => "title_music",
+            Track::Fight => "music",
+            Track::Tension => "tension_music",
+            Track::Victory => "victory_music",
+            Track::Defeat => "defeat_music",
+        }
+    }
+}
+
+impl Default for Stage {
+    fn default() -> Self {
+        Stage::with_projectiles(weapons::load_all())
+    }
+}
+
+impl Stage {
+    /// the original arena with the given weapons, without reading any files.
+    fn with_projectiles(projectiles: [Projectile; 2]) -> Self {
+        Stage {
+            id: DEFAULT_STAGE.to_string(),
+            name: "Arena".to_string(),
+            arena: Arena {
+                projectiles,
+                ..Default::default()
+            },
+            background: [0.5, 0.5, 1.0],
+            layers: Vec::new(),
+            camera_min: Vec2::new(-1000.0, -360.0),
+            camera_max: Vec2::new(1000.0, 900.0),
+            music: Default::default(),
+        }
+    }
+
+    /// parses a stage file. the weapons aren't part of it, they are the ones
+    /// already loaded or sent along with the stage.
+    pub fn from_text(id: &str, text: &str, projectiles: [Projectile; 2]) -> Self {
+        let mut stage = Stage {
+            id: id.to_string(),
+            name: id.to_string(),
+            ..Stage::with_projectiles(projectiles)
+        };
+        stage.arena.platforms.clear();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if !stage.parse_line(line) {
+                warn!("ignoring invalid line in stage {}: {}", id, line);
+            }
+        }
+        stage
+    }
+
+    /// the asset path of the music for `track`, if the stage has one.
+    pub fn music(&self, track: Track) -> Option<&str> {
+        self.music[track as usize].as_deref()
+    }
+
+    fn parse_line(&mut self, line: &str) -> bool {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return false,
+        };
+        let numbers = |text: &str| -> Option<Vec<f32>> {
+            text.split_whitespace()
+                .map(|word| word.parse().ok())
+                .collect()
+        };
+        let vec2 = |numbers: &[f32]| Vec2::new(numbers[0], numbers[1]);
+        if let Some(track) = Track::ALL.into_iter().find(|track| track.key() == key) {
+            self.music[track as usize] = Some(value.to_string());
+            return true;
+        }
+
+        match (key, numbers(value)) {
+            ("name", _) => self.name = value.to_string(),
+            ("background", Some(color)) if color.len() == 3 => {
+                self.background = [color[0], color[1], color[2]];
+            }
+            ("platform" | "one_way", _) => {
+                let (rect, motion) = match value.find(char::is_alphabetic) {
+                    Some(at) => (&value[..at], parse_motion(&value[at..])),
+                    None => (value, Some(Motion::Static)),
+                };
+                match (numbers(rect), motion) {
+                    (Some(rect), Some(motion)) if rect.len() == 4 => {
+                        self.arena.platforms.push(Platform {
+                            pos: vec2(&rect[0..2]),
+                            size: vec2(&rect[2..4]),
+                            one_way: key == "one_way",
+                            motion,
+                        })
+                    }
+                    _ => return false,
+                }
+            }
+            ("spikes" | "lava", Some(rect)) if rect.len() == 4 => {
+                self.arena.hazards.push(Hazard {
+                    kind: if key == "spikes" {
+                        HazardKind::Spikes
+                    } else {
+                        HazardKind::Lava
+                    },
+                    pos: vec2(&rect[0..2]),
+                    size: vec2(&rect[2..4]),
+                });
+            }
+            ("laser", Some(laser)) if laser.len() == 8 => {
+                self.arena.hazards.push(Hazard {
+                    kind: HazardKind::Laser {
+                        sweep: vec2(&laser[4..6]),
+                        period: to_ticks(laser[6]),
+                        active: to_ticks(laser[7]),
+                    },
+                    pos: vec2(&laser[0..2]),
+                    size: vec2(&laser[2..4]),
+                });
+            }
+            ("player_spawn", Some(pos)) if pos.len() == 2 => self.arena.spawns[0] = vec2(&pos),
+            ("opponent_spawn", Some(pos)) if pos.len() == 2 => self.arena.spawns[1] = vec2(&pos),
+            ("blast_zone", Some(rect)) if rect.len() == 4 => {
+                self.arena.blast_min = vec2(&rect[0..2]);
+                self.arena.blast_max = vec2(&rect[2..4]);
+            }
+            ("camera", Some(rect)) if rect.len() == 4 => {
+                self.camera_min = vec2(&rect[0..2]);
+                self.camera_max = vec2(&rect[2..4]);
+            }
+            ("layer", _) => {
+                let (image, rest) = match value.split_once(char::is_whitespace) {
+                    Some(split) => split,
+                    None => return false,
+                };
+                match numbers(rest) {
+                    Some(numbers) if numbers.len() == 5 => self.layers.push(Layer {
+                        image: image.to_string(),
+                        parallax: numbers[0],
+                        pos: vec2(&numbers[1..3]),
+                        size: vec2(&numbers[3..5]),
+                    }),
+                    _ => return false,
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("name = {}\n", self.name);
+        let [r, g, b] = self.background;
+        text.push_str(&format!("background = {} {} {}\n", r, g, b));
+        for layer in self.layers.iter() {
+            text.push_str(&format!(
+                "layer = {} {} {} {} {} {}\n",
+                layer.image, layer.parallax, layer.pos.x, layer.pos.y, layer.size.x, layer.size.y
+            ));
+        }
+        for platform in self.arena.platforms.iter() {
+            text.push_str(&format!(
+                "{} = {} {} {} {}",
+                if platform.one_way {
+                    "one_way"
+                } else {
+                    "platform"
+                },
+                platform.pos.x,
+                platform.pos.y,
+                platform.size.x,
+                platform.size.y
+            ));
+            match platform.motion {
+                Motion::Static => (),
+                Motion::Moving { offset, period } => text.push_str(&format!(
+                    " moves {} {} {}",
+                    offset.x,
+                    offset.y,
+                    to_seconds(period)
+                )),
+                Motion::Falling { delay, respawn } => text.push_str(&format!(
+                    " falls {} {}",
+                    to_seconds(delay),
+                    to_seconds(respawn)
+                )),
+            }
+            text.push('\n');
+        }
+        for hazard in self.arena.hazards.iter() {
+            let (pos, size) = (hazard.pos, hazard.size);
+            text.push_str(&match hazard.kind {
+                HazardKind::Spikes => {
+                    format!("spikes = {} {} {} {}\n", pos.x, pos.y, size.x, size.y)
+                }
+                HazardKind::Lava => format!("lava = {} {} {} {}\n", pos.x, pos.y, size.x, size.y),
+                HazardKind::Laser {
+                    sweep,
+                    period,
+                    active,
+                } => format!(
+                    "laser = {} {} {} {} {} {} {} {}\n",
+                    pos.x,
+                    pos.y,
+                    size.x,
+                    size.y,
+                    sweep.x,
+                    sweep.y,
+                    to_seconds(period),
+                    to_seconds(active)
+                ),
+            });
+        }
+        let [player, opponent] = self.arena.spawns;
+        text.push_str(&format!("player_spawn = {} {}\n", player.x, player.y));
+        text.push_str(&format!("opponent_spawn = {} {}\n", opponent.x, opponent.y));
+        let (min, max) = (self.arena.blast_min, self.arena.blast_max);
+        text.push_str(&format!(
+            "blast_zone = {} {} {} {}\n",
+            min.x, min.y, max.x, max.y
+        ));
+        let (min, max) = (self.camera_min, self.camera_max);
+        text.push_str(&format!(
+            "camera = {} {} {} {}\n",
+            min.x, min.y, max.x, max.y
+        ));
+        for track in Track::ALL {
+            if let Some(music) = self.music(track) {
+                text.push_str(&format!("{} = {}\n", track.key(), music));
+            }
+        }
+        text
+    }
+
+    /// the ids of all stages, sorted.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn ids() -> Vec<String> {
+        let mut ids: Vec<String> = std::fs::read_dir(STAGE_DIR)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "stage")
+            })
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn ids() -> Vec<String> {
+        SHIPPED_STAGES
+            .iter()
+            .map(|(id, _)| id.to_string())
+            .collect()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(id: &str) -> io::Result<Stage> {
+        let path = std::path::Path::new(STAGE_DIR).join(format!("{}.stage", id));
+        let text = std::fs::read_to_string(path)?;
+        Ok(Stage::from_text(id, &text, weapons::load_all()))
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn load(id: &str) -> io::Result<Stage> {
+        SHIPPED_STAGES
+            .iter()
+            .find(|(shipped, _)| *shipped == id)
+            .map(|(_, text)| Stage::from_text(id, text, weapons::load_all()))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such stage"))
+    }
+
+    /// writes the stage to `assets/stages/<id>.stage`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) -> io::Result<()> {
+        std::fs::create_dir_all(STAGE_DIR)?;
+        let path = std::path::Path::new(STAGE_DIR).join(format!("{}.stage", self.id));
+        std::fs::write(path, self.to_text())
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(&self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "stages can't be saved in the browser",
+        ))
+    }
+
+    /// falls back to the original arena if the stage can't be read.
+    pub fn load_or_default(id: &str) -> Stage {
+        Stage::load(id).unwrap_or_else(|err| {
+            warn!("could not load stage {}: {}", id, err);
+            Stage::default()
+        })
+    }
+
+    /// the stage after this one in `ids`, wrapping around.
+    pub fn next(&self) -> Stage {
+        let ids = Stage::ids();
+        let next = match ids.iter().position(|id| *id == self.id) {
+            Some(i) => ids.get(i + 1).or_else(|| ids.first()),
+            None => ids.first(),
+        };
+        match next {
+            Some(id) => Stage::load_or_default(id),
+            None => Stage::default(),
+        }
+    }
+}
+
+/// `moves <x> <y> <seconds>` or `falls <seconds> <seconds>`.
+fn parse_motion(text: &str) -> Option<Motion> {
+    let mut words = text.split_whitespace();
+    let kind = words.next()?;
+    let numbers: Vec<f32> = words.map(|word| word.parse().ok()).collect::<Option<_>>()?;
+    match (kind, numbers.as_slice()) {
+        ("moves", [x, y, seconds]) => Some(Motion::Moving {
+            offset: Vec2::new(*x, *y),
+            period: to_ticks(*seconds),
+        }),
+        ("falls", [delay, respawn]) => Some(Motion::Falling {
+            delay: to_ticks(*delay),
+            respawn: to_ticks(*respawn),
+        }),
+        _ => None,
+    }
+}
+
+pub fn to_ticks(seconds: f32) -> u32 {
+    (seconds * sim::TICK_RATE as f32).round().max(0.0) as u32
+}
+
+pub fn to_seconds(ticks: u32) -> f32 {
+    ticks as f32 / sim::TICK_RATE as f32
+}
+
+#[derive(Component)]
+pub struct StageSprite;
+
+#[derive(Component)]
+pub struct PlatformSprite {
+    /// index into `arena.platforms`
+    index: usize,
+}
+
+#[derive(Component)]
+pub struct HazardSprite {
+    /// index into `arena.hazards`
+    index: usize,
+}
+
+#[derive(Component)]
+pub struct LayerSprite {
+    parallax: f32,
+    pos: Vec2,
+}
+
+/// rebuilds the stage sprites whenever the stage changes and moves the
+/// layers with the camera.
+pub fn update_stage_sprites(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    server: Res<AssetServer>,
+    mut shown: Local<Option<Stage>>,
+    stage_query: Query<Entity, With<StageSprite>>,
+    camera_query: Query<(&Camera, &Transform), Without<LayerSprite>>,
+    mut layer_query: Query<(&LayerSprite, &mut Transform)>,
+) {
+    if shown.as_ref() != Some(&sim.stage) {
+        for entity in stage_query.iter() {
+            commands.entity(entity).despawn();
+        }
+        spawn_stage(&mut commands, &server, &sim.stage);
+        *shown = Some(sim.stage.clone());
+    }
+
+    for (camera, camera_transform) in camera_query.iter() {
+        if camera.name.as_deref() != Some(CameraPlugin::CAMERA_2D) {
+            continue;
+        }
+        let camera_pos = camera_transform.translation.truncate();
+        for (layer, mut transform) in layer_query.iter_mut() {
+            let pos = layer.pos + camera_pos * layer.parallax;
+            transform.translation.x = pos.x;
+            transform.translation.y = pos.y;
+        }
+    }
+}
+
+fn spawn_stage(commands: &mut Commands, server: &AssetServer, stage: &Stage) {
+    let [r, g, b] = stage.background;
+    commands
+        .spawn_bundle(SpriteBundle {
+            transform: Transform {
+                translation: Vec3::new(0.0, 0.0, -10.0),
+                scale: Vec3::new(9000.0, 9000.0, 0.0),
+                ..Default::default()
+            },
+            sprite: Sprite {
+                color: Color::rgb(r, g, b),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(StageSprite);
+
+    for (i, layer) in stage.layers.iter().enumerate() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                texture: server.load(layer.image.as_str()),
+                transform: Transform::from_translation(layer.pos.extend(-9.0 + i as f32 * 0.01)),
+                sprite: Sprite {
+                    custom_size: Some(layer.size),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(StageSprite)
+            .insert(LayerSprite {
+                parallax: layer.parallax,
+                pos: layer.pos,
+            });
+    }
+
+    for (index, platform) in stage.arena.platforms.iter().enumerate() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                transform: Transform {
+                    translation: platform.pos.extend(-1.0),
+                    scale: platform.size.extend(0.0),
+                    ..Default::default()
+                },
+                sprite: Sprite {
+                    color: platform_color(platform, false),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(StageSprite)
+            .insert(PlatformSprite { index });
+    }
+
+    for (index, hazard) in stage.arena.hazards.iter().enumerate() {
+        // lasers go over the fighters, the rest is something to fall into
+        let z = match hazard.kind {
+            HazardKind::Laser { .. } => 2.0,
+            _ => -0.5,
+        };
+        commands
+            .spawn_bundle(SpriteBundle {
+                transform: Transform {
+                    translation: hazard.pos.extend(z),
+                    scale: hazard.size.extend(0.0),
+                    ..Default::default()
+                },
+                sprite: Sprite {
+                    color: Color::NONE,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(StageSprite)
+            .insert(HazardSprite { index });
+    }
+}
+
+fn platform_color(platform: &Platform, crumbling: bool) -> Color {
+    match (platform.one_way, crumbling) {
+        (_, true) => Color::rgb(0.55, 0.45, 0.35),
+        (true, false) => Color::rgb(0.3, 0.3, 0.3),
+        (false, false) => Color::rgb(0.0, 0.0, 0.0),
+    }
+}
+
+/// moves the platforms and hazards along with the simulation.
+pub fn update_platform_sprites(
+    sim: Res<Sim>,
+    mut platform_query: Query<
+        (&PlatformSprite, &mut Transform, &mut Sprite),
+        Without<HazardSprite>,
+    >,
+    mut hazard_query: Query<(&HazardSprite, &mut Transform, &mut Sprite), Without<PlatformSprite>>,
+) {
+    let simulation = &sim.simulation;
+    for (platform_sprite, mut transform, mut sprite) in platform_query.iter_mut() {
+        let (platform, state) = match (
+            simulation.arena.platforms.get(platform_sprite.index),
+            simulation.platforms.get(platform_sprite.index),
+        ) {
+            (Some(platform), Some(state)) => (platform, state),
+            _ => continue,
+        };
+        transform.translation.x = state.pos.x;
+        transform.translation.y = state.pos.y;
+        sprite.color = platform_color(platform, state.is_crumbling());
+    }
+
+    for (hazard_sprite, mut transform, mut sprite) in hazard_query.iter_mut() {
+        let hazard = match simulation.arena.hazards.get(hazard_sprite.index) {
+            Some(hazard) => hazard,
+            None => continue,
+        };
+        // a laser that is off shows its warning where it starts
+        let firing = hazard.pos_at(simulation.tick);
+        let pos = firing.unwrap_or(hazard.pos);
+        transform.translation.x = pos.x;
+        transform.translation.y = pos.y;
+        sprite.color = match hazard.kind {
+            HazardKind::Spikes => Color::rgb(0.45, 0.4, 0.45),
+            HazardKind::Lava => Color::rgb(1.0, 0.35, 0.05),
+            HazardKind::Laser { .. } if firing.is_some() => Color::rgba(1.0, 0.1, 0.1, 0.9),
+            HazardKind::Laser { .. } if hazard.is_warning(simulation.tick) => {
+                Color::rgba(1.0, 0.1, 0.1, 0.25)
+            }
+            HazardKind::Laser { .. } => Color::NONE,
+        };
+    }
+}
@@ -0,0 +1,162 @@
+//! How the bullets of each weapon fly.
+//!
+//! Each weapon has a file, `assets/weapons/<weapon>.weapon`, with one
+//! `key = value` line per setting:
+//!
+//! ```text
+//! speed = 0 600, 0.25 400, 1 250
+//! gravity = 150
+//! turn_rate = 0
+//! ricochets = 1
+//! pierces = 0
+//! lifetime = 2
+//! ```
+//!
+//! `speed` lists keys of seconds since the shot and the speed in units per
+//! second from then on, in order; the speed changes linearly between keys
+//! and stays at the last one. `gravity` pulls the bullet down in units per
+//! second squared, so it flies in an arc. `turn_rate` is how many radians
+//! per second it turns towards the fighter it was fired at. `ricochets` is
+//! how many times it bounces off solid platforms and `pierces` through how
+//! many fighters it flies on after hitting them. `lifetime` is in seconds.
+//! A setting that is left out keeps the value of a plain bullet flying
+//! straight ahead.
+//!
+//! The data is part of the match: replays store it and the host sends it to
+//! the guest, so both fly the same bullets.
+
+use bevy::prelude::*;
+
+use std::io;
+
+use crate::sim::{Projectile, Weapon};
+use crate::stage::{to_seconds, to_ticks};
+
+#[cfg(not(target_arch = "wasm32"))]
+const WEAPON_DIR: &str = "assets/weapons";
+
+// the browser build has no file system to read the weapons from
+#[cfg(target_arch = "wasm32")]
+const SHIPPED_WEAPONS: [(&str, &str); 2] = [
+    ("gun", include_str!("../assets/weapons/gun.weapon")),
+    ("shotgun", include_str!("../assets/weapons/shotgun.weapon")),
+];
+
+fn weapon_name(weapon: Weapon) -> &'static str {
+    match weapon {
+        Weapon::Gun => "gun",
+        Weapon::Shotgun => "shotgun",
+    }
+}
+
+pub fn from_text(name: &str, text: &str) -> Projectile {
+    let mut projectile = Projectile::default();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line.split_once('=').and_then(|(key, value)| {
+            let (key, value) = (key.trim(), value.trim());
+            let number = || {
+                value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|number| number.is_finite())
+            };
+            match key {
+                "speed" => projectile.speed = parse_speed(value)?,
+                "gravity" => projectile.gravity = number()?,
+                "turn_rate" => projectile.turn_rate = number()?.max(0.0),
+                "ricochets" => projectile.ricochets = value.parse().ok()?,
+                "pierces" => projectile.pierces = value.parse().ok()?,
+                "lifetime" => match to_ticks(number()?) {
+                    0 => return None,
+                    ticks => projectile.lifetime_ticks = ticks,
+                },
+                _ => return None,
+            }
+            Some(())
+        });
+        if parsed.is_none() {
+            warn!("ignoring invalid line in weapon {}: {}", name, line);
+        }
+    }
+    projectile
+}
+
+/// `<seconds> <speed>` keys separated by commas, in order.
+fn parse_speed(value: &str) -> Option<Vec<(f32, f32)>> {
+    let keys = value
+        .split(',')
+        .map(|key| {
+            let numbers: Vec<f32> = key
+                .split_whitespace()
+                .map(|word| word.parse().ok().filter(|number: &f32| number.is_finite()))
+                .collect::<Option<_>>()?;
+            match numbers.as_slice() {
+                [time, speed] => Some((*time, *speed)),
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let in_order = keys.windows(2).all(|pair| pair[0].0 < pair[1].0);
+    (!keys.is_empty() && in_order).then(|| keys)
+}
+
+pub fn to_text(projectile: &Projectile) -> String {
+    let speed: Vec<String> = projectile
+        .speed
+        .iter()
+        .map(|(time, speed)| format!("{} {}", time, speed))
+        .collect();
+    format!(
+        "speed = {}\ngravity = {}\nturn_rate = {}\nricochets = {}\npierces = {}\nlifetime = {}\n",
+        speed.join(", "),
+        projectile.gravity,
+        projectile.turn_rate,
+        projectile.ricochets,
+        projectile.pierces,
+        to_seconds(projectile.lifetime_ticks)
+    )
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load(name: &str) -> io::Result<Projectile> {
+    let path = std::path::Path::new(WEAPON_DIR).join(format!("{}.weapon", name));
+    Ok(from_text(name, &std::fs::read_to_string(path)?))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn load(name: &str) -> io::Result<Projectile> {
+    SHIPPED_WEAPONS
+        .iter()
+        .find(|(shipped, _)| *shipped == name)
+        .map(|(_, text)| from_text(name, text))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such weapon"))
+}
+
+/// the projectiles of all weapons, by `Weapon`. a weapon that can't be read
+/// fires plain bullets.
+pub fn load_all() -> [Projectile; 2] {
+    Weapon::ALL.map(|weapon| {
+        let name = weapon_name(weapon);
+        load(name).unwrap_or_else(|err| {
+            warn!("could not load weapon {}: {}", name, err);
+            Projectile::default()
+        })
+    })
+}
+
+/// the projectiles of all weapons as text, by `Weapon`, for replays and the
+/// start of an online match.
+pub fn all_to_text(projectiles: &[Projectile; 2]) -> [String; 2] {
+    [to_text(&projectiles[0]), to_text(&projectiles[1])]
+}
+
+pub fn all_from_text(texts: [&str; 2]) -> [Projectile; 2] {
+    let [gun, shotgun] = texts;
+    [
+        from_text(weapon_name(Weapon::Gun), gun),
+        from_text(weapon_name(Weapon::Shotgun), shotgun),
+    ]
+}
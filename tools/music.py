@@ -0,0 +1,278 @@
+#!/usr/bin/env python3
+"""Writes the music of the game to assets/audio/music/ as wav files.
+
+The tracks are small chiptune loops made of two pulse waves, a triangle bass
+and noise drums, generated from the note lists below. Run it from the
+repository root with any python 3, it needs nothing else:
+
+    python3 tools/music.py
+
+and copy assets/audio/music/ to web/assets/audio/music/ for the browser build.
+The output is the same on every run.
+"""
+
+import math
+import os
+import random
+import struct
+import wave
+
+RATE = 22050
+OUT_DIR = os.path.join("assets", "audio", "music")
+
+NOTE_NAMES = {"C": 0, "D": 2, "E": 4, "F": 5, "G": 7, "A": 9, "B": 11}
+
+
+def midi(name):
+    """`A4`, `C#5` or `Bb3` to a midi note number."""
+    note = NOTE_NAMES[name[0]]
+    rest = name[1:]
+    if rest.startswith("#"):
+        note, rest = note + 1, rest[1:]
+    elif rest.startswith("b"):
+        note, rest = note - 1, rest[1:]
+    return note + 12 * (int(rest) + 1)
+
+
+def freq(note):
+    return 440.0 * 2 ** ((note - 69) / 12)
+
+
+def parse(notes):
+    """`A4:2 -:2 C5:4` to (step, length, midi note) in sixteenths, `-` rests."""
+    events, step = [], 0
+    for word in notes.split():
+        name, length = word.split(":")
+        length = int(length)
+        if name != "-":
+            events.append((step, length, midi(name)))
+        step += length
+    return events
+
+
+class Track:
+    def __init__(self, bpm, bars, looped=True):
+        self.step_len = 60.0 / bpm / 4
+        self.steps = bars * 16
+        self.samples = [0.0] * round(self.steps * self.step_len * RATE)
+        self.looped = looped
+        self.noise = random.Random(7)
+
+    def at(self, step):
+        return round(step * self.step_len * RATE)
+
+    def add(self, start, values):
+        """adds `values` from sample `start` on. a note ringing past the end
+        of a loop is heard at its start, one that is played once gets longer."""
+        length = len(self.samples)
+        if not self.looped and start + len(values) > length:
+            self.samples.extend([0.0] * (start + len(values) - length))
+            length = len(self.samples)
+        for i, value in enumerate(values):
+            self.samples[(start + i) % length] += value
+
+    def tone(self, voice, notes, volume, duty=0.5, release=0.03):
+        for step, length, note in parse(notes):
+            self.add(
+                self.at(step),
+                tone(voice, freq(note), length * self.step_len, volume, duty, release),
+            )
+
+    def drums(self, pattern, volume):
+        """one character per sixteenth, `k` kick, `s` snare, `h` hat."""
+        pattern = pattern.replace(" ", "")
+        for step in range(self.steps):
+            hit = pattern[step % len(pattern)]
+            if hit in DRUMS:
+                self.add(self.at(step), DRUMS[hit](self.noise, volume))
+
+    def write(self, name, peak=0.8):
+        top = max(abs(value) for value in self.samples) or 1.0
+        frames = b"".join(
+            struct.pack("<h", round(value / top * peak * 32767)) for value in self.samples
+        )
+        path = os.path.join(OUT_DIR, name + ".wav")
+        with wave.open(path, "wb") as out:
+            out.setnchannels(1)
+            out.setsampwidth(2)
+            out.setframerate(RATE)
+            out.writeframes(frames)
+        print("wrote {} ({:.1f} s)".format(path, len(self.samples) / RATE))
+
+
+def tone(voice, hz, seconds, volume, duty, release):
+    count = round((seconds + release) * RATE)
+    held = seconds * RATE
+    values = []
+    phase = 0.0
+    for i in range(count):
+        phase = (phase + hz / RATE) % 1.0
+        if voice == "pulse":
+            value = 1.0 if phase < duty else -1.0
+        else:
+            value = 4.0 * abs(phase - 0.5) - 1.0
+        attack = min(1.0, i / (0.004 * RATE))
+        decay = 0.6 + 0.4 * math.exp(-i / (0.08 * RATE))
+        fade = 1.0 if i < held else max(0.0, 1.0 - (i - held) / (release * RATE))
+        values.append(value * volume * attack * decay * fade)
+    return values
+
+
+def kick(noise, volume):
+    values, phase = [], 0.0
+    for i in range(round(0.15 * RATE)):
+        t = i / RATE
+        phase += (45.0 + 110.0 * math.exp(-t * 30.0)) / RATE
+        values.append(math.sin(2 * math.pi * phase) * math.exp(-t * 22.0) * volume)
+    return values
+
+
+def snare(noise, volume):
+    values = []
+    for i in range(round(0.14 * RATE)):
+        t = i / RATE
+        body = 0.4 * math.sin(2 * math.pi * 185.0 * t)
+        values.append((noise.uniform(-1, 1) + body) * math.exp(-t * 28.0) * volume * 0.7)
+    return values
+
+
+def hat(noise, volume):
+    values, last = [], 0.0
+    for i in range(round(0.04 * RATE)):
+        value = noise.uniform(-1, 1)
+        # the difference of two samples keeps the hiss and drops the rumble
+        values.append((value - last) * 0.5 * math.exp(-i / RATE * 110.0) * volume * 0.4)
+        last = value
+    return values
+
+
+DRUMS = {"k": kick, "s": snare, "h": hat}
+
+
+def arpeggio(chords, pattern, length=2):
+    """`Am F` with `0 1 2 1` to eighth notes over each chord, a bar each."""
+    shapes = {
+        "": (0, 4, 7, 12),
+        "m": (0, 3, 7, 12),
+    }
+    words = []
+    for chord in chords.split():
+        root, quality = chord.rstrip("m"), "m" if chord.endswith("m") else ""
+        base = midi(root + "4")
+        steps = [int(index) for index in pattern.split()]
+        for i in range(16 // length):
+            note = base + shapes[quality][steps[i % len(steps)]]
+            words.append("{}:{}".format(name_of(note), length))
+    return " ".join(words)
+
+
+def name_of(note):
+    names = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"]
+    return "{}{}".format(names[note % 12], note // 12 - 1)
+
+
+def title():
+    track = Track(bpm=96, bars=4)
+    track.tone("pulse", arpeggio("Am F C G", "0 1 2 3 2 1"), 0.12, duty=0.125)
+    track.tone(
+        "pulse",
+        "E5:6 D5:2 C5:4 B4:4 A4:6 C5:2 F5:8 E5:6 D5:2 C5:4 G4:4 B4:8 D5:8",
+        0.16,
+        duty=0.25,
+        release=0.15,
+    )
+    track.tone("triangle", "A2:8 A2:8 F2:8 F2:8 C3:8 C3:8 G2:8 G2:8", 0.5)
+    track.drums("h.......h.......", 0.5)
+    track.write("title")
+
+
+def fight():
+    track = Track(bpm=150, bars=8)
+    lead = (
+        "E5:2 -:2 E5:2 G5:2 -:2 E5:2 D5:2 B4:2 "
+        "C5:4 E5:2 C5:2 G4:4 -:4 "
+        "D5:2 -:2 D5:2 F#5:2 -:2 D5:2 A4:2 D5:2 "
+        "B4:6 F#4:2 B4:4 D#5:4 "
+    )
+    answer = (
+        "G5:2 F#5:2 E5:2 B4:2 -:2 E5:2 G5:2 B5:2 "
+        "A5:4 G5:2 E5:2 C5:4 E5:4 "
+        "F#5:2 E5:2 D5:2 A4:2 -:2 D5:2 F#5:2 A5:2 "
+        "B5:4 A5:2 F#5:2 D#5:8 "
+    )
+    track.tone("pulse", lead + answer, 0.15, duty=0.25)
+    track.tone("pulse", arpeggio("Em C D B Em C D B", "0 2 1 2"), 0.07, duty=0.125)
+    bass = "E2:2 E3:2 " * 4 + "C2:2 C3:2 " * 4 + "D2:2 D3:2 " * 4 + "B1:2 B2:2 " * 4
+    track.tone("triangle", bass * 2, 0.5)
+    track.drums("k.h.s.h.k.k.s.hh", 0.6)
+    track.write("fight")
+
+
+def fight_night():
+    track = Track(bpm=138, bars=8)
+    lead = (
+        "D5:3 F5:3 A5:2 G5:4 F5:4 "
+        "Bb4:3 D5:3 F5:2 E5:4 D5:4 "
+        "C5:3 E5:3 G5:2 F5:2 E5:2 C5:4 "
+        "A4:4 C#5:4 E5:4 -:4 "
+    )
+    answer = (
+        "A5:3 G5:3 F5:2 E5:4 D5:4 "
+        "F5:3 E5:3 D5:2 Bb4:8 "
+        "G5:3 F5:3 E5:2 C5:4 E5:4 "
+        "D5:8 C#5:4 A4:4 "
+    )
+    track.tone("pulse", lead + answer, 0.15, duty=0.5, release=0.06)
+    track.tone("pulse", arpeggio("Dm A# C A Dm A# C A", "0 1 2 1"), 0.06, duty=0.25)
+    bass = (
+        "D2:3 D2:3 D3:2 " * 2
+        + "Bb1:3 Bb1:3 Bb2:2 " * 2
+        + "C2:3 C2:3 C3:2 " * 2
+        + "A1:3 A1:3 A2:2 " * 2
+    )
+    track.tone("triangle", bass * 2, 0.5)
+    track.drums("k..hs..hk.khs..h", 0.6)
+    track.write("fight_night")
+
+
+def tension():
+    track = Track(bpm=172, bars=4)
+    ostinato = "C5:1 G4:1 Eb5:1 G4:1 " * 4
+    track.tone(
+        "pulse",
+        ostinato * 2 + ("Ab4:1 Eb4:1 C5:1 Eb4:1 " * 4) + ("G4:1 D4:1 B4:1 D4:1 " * 4),
+        0.09,
+        duty=0.125,
+    )
+    track.tone("pulse", "C6:8 Bb5:8 G5:16 Ab5:8 G5:8 F5:8 D5:8", 0.13, duty=0.25, release=0.1)
+    bass = "C2:1 C2:1 C3:1 C2:1 " * 8 + "Ab1:1 Ab1:1 Ab2:1 Ab1:1 " * 4 + "G1:1 G1:1 G2:1 G1:1 " * 4
+    track.tone("triangle", bass, 0.5)
+    track.drums("k.hhs.hhk.hhs.hk", 0.65)
+    track.write("tension")
+
+
+def victory():
+    track = Track(bpm=140, bars=2, looped=False)
+    track.tone("pulse", "C5:1 E5:1 G5:1 C6:5 G5:2 A5:2 B5:2 C6:16", 0.18, duty=0.25, release=0.3)
+    track.tone("pulse", "E4:1 G4:1 C5:1 E5:5 E5:2 F5:2 G5:2 E5:16", 0.1, duty=0.125, release=0.3)
+    track.tone("triangle", "C3:8 F2:2 G2:2 G2:4 C2:16", 0.55, release=0.3)
+    track.drums("k.......k.k.s.s.k...............", 0.6)
+    track.write("victory")
+
+
+def defeat():
+    track = Track(bpm=88, bars=2, looped=False)
+    track.tone("pulse", "E5:4 D5:4 C5:4 B4:4 A4:4 G#4:4 A4:8", 0.16, duty=0.5, release=0.4)
+    track.tone("pulse", "C5:8 A4:8 F4:8 E4:8", 0.07, duty=0.125, release=0.4)
+    track.tone("triangle", "A2:8 F2:8 E2:8 A1:8", 0.5, release=0.4)
+    track.write("defeat")
+
+
+if __name__ == "__main__":
+    os.makedirs(OUT_DIR, exist_ok=True)
+    title()
+    fight()
+    fight_night()
+    tension()
+    victory()
+    defeat()
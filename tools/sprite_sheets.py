@@ -0,0 +1,151 @@
+#!/usr/bin/env python3
+"""Writes the animation sheets of the fighters from their still images.
+
+Every frame is the still image with the torso squashed or stretched by a few
+rows and the legs lifted, so the outline of the art stays untouched. The
+frames go row by row into a grid of `COLUMNS` columns, in the order of
+`FRAMES`; assets/animations/<role>.anim picks the clips out of them. Run it
+from the repository root with any python 3, it needs nothing else:
+
+    python3 tools/sprite_sheets.py
+
+and copy the sheets to web/assets/ for the browser build.
+"""
+
+import os
+import struct
+import zlib
+
+ROLES = ["boxer", "weapon_specialist"]
+COLUMNS = 7
+
+# a row in the middle of the torso, all rows around it look the same
+TORSO_ROW = 55
+# the legs run from this row to the bottom, split at `LEG_SPLIT` columns
+LEG_TOP = 73
+LEG_SPLIT = 11
+
+# (name, torso rows added or removed, rows the back leg and front leg are
+# lifted by); the fighters face right, the back leg is the left one. the hat
+# of the weapon specialist leaves room for one added row
+FRAMES = [
+    ("idle 0", 0, 0, 0),
+    ("idle 1", -1, 0, 0),
+    ("walk 0", 1, 5, 0),
+    ("walk 1", 0, 0, 0),
+    ("walk 2", 1, 0, 5),
+    ("walk 3", 0, 0, 0),
+    ("punch 0", -2, 0, 0),
+    ("punch 1", 1, 4, 0),
+    ("punch 2", -1, 0, 0),
+    ("hit 0", -3, 0, 0),
+    ("hit 1", -1, 0, 0),
+    ("jump", 1, 6, 6),
+    ("fall", 0, 2, 2),
+    ("ko", -4, 0, 0),
+]
+
+CLEAR = (0, 0, 0, 0)
+
+
+def read_png(path):
+    """the rows of rgba pixels of an 8 bit rgba png."""
+    data = open(path, "rb").read()
+    pos, idat = 8, b""
+    while pos < len(data):
+        (length,) = struct.unpack(">I", data[pos : pos + 4])
+        kind, body = data[pos + 4 : pos + 8], data[pos + 8 : pos + 8 + length]
+        pos += 12 + length
+        if kind == b"IHDR":
+            width, height, depth, color = struct.unpack(">IIBB", body[:10])
+            assert depth == 8 and color == 6, "only 8 bit rgba pngs"
+        elif kind == b"IDAT":
+            idat += body
+    raw = zlib.decompress(idat)
+    stride = width * 4
+    rows, above, i = [], bytearray(stride), 0
+    for _ in range(height):
+        kind, line = raw[i], bytearray(raw[i + 1 : i + 1 + stride])
+        i += 1 + stride
+        for x in range(stride):
+            left = line[x - 4] if x >= 4 else 0
+            up = above[x]
+            up_left = above[x - 4] if x >= 4 else 0
+            if kind == 1:
+                line[x] = (line[x] + left) & 255
+            elif kind == 2:
+                line[x] = (line[x] + up) & 255
+            elif kind == 3:
+                line[x] = (line[x] + (left + up) // 2) & 255
+            elif kind == 4:
+                guess = left + up - up_left
+                nearest = min(
+                    (abs(guess - left), 0, left),
+                    (abs(guess - up), 1, up),
+                    (abs(guess - up_left), 2, up_left),
+                )[2]
+                line[x] = (line[x] + nearest) & 255
+        rows.append([tuple(line[x * 4 : x * 4 + 4]) for x in range(width)])
+        above = line
+    return rows
+
+
+def write_png(path, rows):
+    def chunk(kind, body):
+        crc = zlib.crc32(kind + body) & 0xFFFFFFFF
+        return struct.pack(">I", len(body)) + kind + body + struct.pack(">I", crc)
+
+    header = struct.pack(">IIBBBBB", len(rows[0]), len(rows), 8, 6, 0, 0, 0)
+    raw = b"".join(b"\0" + bytes(value for pixel in row for value in pixel) for row in rows)
+    with open(path, "wb") as out:
+        out.write(b"\x89PNG\r\n\x1a\n")
+        out.write(chunk(b"IHDR", header))
+        out.write(chunk(b"IDAT", zlib.compress(raw, 9)))
+        out.write(chunk(b"IEND", b""))
+
+
+def torso(rows, delta):
+    """adds `delta` rows to the torso, or removes them, keeping the feet on
+    the ground."""
+    width = len(rows[0])
+    if delta < 0:
+        blank = [[CLEAR] * width for _ in range(-delta)]
+        return blank + rows[:TORSO_ROW] + rows[TORSO_ROW - delta :]
+    assert all(pixel[3] == 0 for row in rows[:delta] for pixel in row), "no room above"
+    return rows[delta:TORSO_ROW] + [rows[TORSO_ROW]] * delta + rows[TORSO_ROW:]
+
+
+def lift(rows, columns, by):
+    """pulls the foot in `columns` up by `by` rows."""
+    rows = [list(row) for row in rows]
+    bottom = len(rows)
+    for y in range(LEG_TOP + 1, bottom):
+        for x in columns:
+            rows[y][x] = rows[y + by][x] if y + by < bottom else CLEAR
+    return rows
+
+
+def frame(still, delta, back, front):
+    width = len(still[0])
+    rows = torso(still, delta)
+    rows = lift(rows, range(0, LEG_SPLIT), back)
+    return lift(rows, range(LEG_SPLIT, width), front)
+
+
+def sheet(still):
+    width, height = len(still[0]), len(still)
+    grid_rows = (len(FRAMES) + COLUMNS - 1) // COLUMNS
+    rows = [[CLEAR] * (width * COLUMNS) for _ in range(height * grid_rows)]
+    for index, (_, delta, back, front) in enumerate(FRAMES):
+        left, top = index % COLUMNS * width, index // COLUMNS * height
+        for y, row in enumerate(frame(still, delta, back, front)):
+            rows[top + y][left : left + width] = row
+    return rows
+
+
+if __name__ == "__main__":
+    for role in ROLES:
+        still = read_png(os.path.join("assets", role + ".png"))
+        path = os.path.join("assets", role + "_sheet.png")
+        write_png(path, sheet(still))
+        print("wrote {}".format(path))